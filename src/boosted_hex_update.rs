@@ -1,26 +1,24 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_proto::{BoostedHexUpdateV1, Message};
-use sqlx::{Pool, Postgres};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 
-use crate::{to_datetime, to_optional_datetime, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, to_optional_datetime, CleanTarget, DbTable, Decode, Insertable, InsertableStream,
+    Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeBoostedHexUpdate {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeBoostedHexUpdate {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(
-                |buf| async move { BoostedHexUpdateV1::decode(buf).map_err(anyhow::Error::from) },
-            )
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, BoostedHexUpdateV1::decode)
     }
 }
 
@@ -42,7 +40,9 @@ impl DbTable for FileTypeBoostedHexUpdate {
                     period_length int,
                     multipliers integer[],
                     version int,
-                    written_timestamp timestamptz not null
+                    written_timestamp timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -51,30 +51,84 @@ impl DbTable for FileTypeBoostedHexUpdate {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "boosted_hex_updates",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["boosted_hex_updates"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "boosted_hex_updates_location_idx",
+            table: "boosted_hex_updates",
+            columns: "location",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<BoostedHexUpdateV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        for report in self {
-            let update = report.clone().update.unwrap();
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let rows = errors.rows(self, |report| {
+            let update = report
+                .update
+                .clone()
+                .ok_or(RecordError::MissingField("update"))?;
+            let start_ts = to_optional_datetime(update.start_ts)?;
+            let end_ts = to_optional_datetime(update.end_ts)?;
+            let timestamp = to_datetime(report.timestamp)?;
+            Ok((update, start_ts, end_ts, timestamp))
+        })?;
+
+        let written = rows.len();
+        for (update, start_ts, end_ts, timestamp) in rows {
             sqlx::query(r#"
-                INSERT INTO boosted_hex_updates(location, start_ts, end_ts, period_length, multipliers, version, written_timestamp) values($1,$2,$3,$4,$5,$6,$7)
+                INSERT INTO boosted_hex_updates(location, start_ts, end_ts, period_length, multipliers, version, written_timestamp, file_key, file_timestamp) values($1,$2,$3,$4,$5,$6,$7,$8,$9)
             "#)
             .bind(update.location as i64)
-            .bind(to_optional_datetime(update.start_ts))
-            .bind(to_optional_datetime(update.end_ts))
+            .bind(start_ts)
+            .bind(end_ts)
             .bind(update.period_length as i32)
             .bind::<Vec<i32>>(update.multipliers.into_iter().map(|u| u as i32).collect())
             .bind(update.version as i32)
-            .bind(to_datetime(report.timestamp))
-            .execute(db)
+            .bind(timestamp)
+            .bind(&file_info.key)
+            .bind(file_info.timestamp)
+            .execute(&mut *transaction)
             .await?;
         }
-        Ok(())
+        Ok(written)
+    }
+}
+
+impl Record for BoostedHexUpdateV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime(self.timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let update = self.update.as_ref();
+        json!({
+            "timestamp": to_datetime(self.timestamp).ok(),
+            "location": update.map(|u| u.location),
+            "start_ts": update.and_then(|u| to_optional_datetime(u.start_ts).ok().flatten()),
+            "end_ts": update.and_then(|u| to_optional_datetime(u.end_ts).ok().flatten()),
+            "period_length": update.map(|u| u.period_length),
+            "multipliers": update.map(|u| &u.multipliers),
+            "version": update.map(|u| u.version),
+        })
     }
 }
@@ -1,27 +1,24 @@
 use chrono::{DateTime, Utc};
 use file_store::{
-    speedtest::CellSpeedtestIngestReport, traits::MsgDecode, BytesMutStream, FileType,
+    speedtest::CellSpeedtestIngestReport, traits::MsgDecode, BytesMutStream, FileInfo, FileType,
 };
-use futures::TryStreamExt;
-use sqlx::{Pool, Postgres};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 
-use crate::{DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::RecordErrors,
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeCellSpeedtestIngestReport {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeCellSpeedtestIngestReport {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                CellSpeedtestIngestReport::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, CellSpeedtestIngestReport::decode)
     }
 }
 
@@ -43,7 +40,9 @@ impl DbTable for FileTypeCellSpeedtestIngestReport {
                   received_timestamp timestamptz not null,
                   upload_speed bigint not null,
                   download_speed bigint not null,
-                  latency integer not null
+                  latency integer not null,
+                  file_key text,
+                  file_timestamp timestamptz
                 )
             "#,
         )
@@ -52,21 +51,43 @@ impl DbTable for FileTypeCellSpeedtestIngestReport {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "mobile_speedtest_ingest_reports",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["mobile_speedtest_ingest_reports"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "mobile_speedtest_ingest_reports_hotspot_key_idx",
+            table: "mobile_speedtest_ingest_reports",
+            columns: "hotspot_key, timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<CellSpeedtestIngestReport> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        _errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
         for test in self {
             sqlx::query(
                 r#"
                 INSERT INTO mobile_speedtest_ingest_reports(hotspot_key, serial, timestamp,
-                    received_timestamp, upload_speed, download_speed, latency)
-                VALUES($1,$2,$3,$4,$5,$6,$7)
+                    received_timestamp, upload_speed, download_speed, latency, file_key, file_timestamp)
+                VALUES($1,$2,$3,$4,$5,$6,$7,$8,$9)
             "#,
             )
             .bind(test.report.pubkey.to_string())
@@ -76,9 +97,29 @@ impl Insertable for Vec<CellSpeedtestIngestReport> {
             .bind(test.report.upload_speed as i64)
             .bind(test.report.download_speed as i64)
             .bind(test.report.latency as i64)
-            .execute(db)
+            .bind(&file_info.key)
+            .bind(file_info.timestamp)
+            .execute(&mut *transaction)
             .await?;
         }
-        Ok(())
+        Ok(self.len())
+    }
+}
+
+impl Record for CellSpeedtestIngestReport {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.received_timestamp)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "received_timestamp": self.received_timestamp,
+            "hotspot_key": self.report.pubkey.to_string(),
+            "serial": self.report.serial,
+            "timestamp": self.report.timestamp,
+            "upload_speed": self.report.upload_speed,
+            "download_speed": self.report.download_speed,
+            "latency": self.report.latency,
+        })
     }
 }
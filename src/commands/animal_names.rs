@@ -1,7 +1,6 @@
 use std::path::PathBuf;
 
 use angry_purple_tiger::AnimalName;
-use clap::arg;
 
 #[derive(Debug, clap::Args)]
 pub struct AnimalNames {
@@ -1,20 +1,229 @@
-use crate::SupportedFileTypes;
+use std::{io::Write, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use file_store::FileInfo;
+use sqlx::{Pool, Postgres};
+
+use crate::{processed_files, CleanTarget, SupportedFileTypes};
 
 use super::{DbArgs, TimeArgs};
 
 #[derive(Debug, clap::Args)]
 pub struct Clean {
-    #[arg(short, long)]
+    #[arg(short, long, required_unless_present = "file_key")]
     file_type: Option<SupportedFileTypes>,
+    /// Delete only the rows imported from this file, so it can be imported again
+    #[arg(long, conflicts_with_all = ["file_type", "after", "before"])]
+    file_key: Option<String>,
+    /// Delete without asking for confirmation
+    #[arg(short, long)]
+    yes: bool,
     #[command(flatten)]
     db: DbArgs,
     #[command(flatten)]
     time: TimeArgs,
 }
 
+enum Scope {
+    Window(Option<DateTime<Utc>>, Option<DateTime<Utc>>),
+    File(String),
+}
+
+impl Scope {
+    fn clause(&self, target: &CleanTarget) -> String {
+        match self {
+            Scope::Window(..) => target.from_where(),
+            Scope::File(_) => target.from_file_key(),
+        }
+    }
+}
+
 impl Clean {
     pub async fn run(self) -> anyhow::Result<()> {
-        println!("clean");
+        let db = self.db.connect().await?;
+
+        let (scope, file_types) = match &self.file_key {
+            Some(file_key) => (Scope::File(file_key.clone()), file_types_for_key(file_key)?),
+            None => {
+                let Some(file_type) = &self.file_type else {
+                    anyhow::bail!("either --file-type or --file-key is required");
+                };
+                let scope = Scope::Window(self.time.after_utc(), self.time.before_utc());
+                (scope, vec![file_type.clone()])
+            }
+        };
+
+        let mut targets: Vec<(CleanTarget, i64)> = vec![];
+        for target in file_types
+            .iter()
+            .flat_map(SupportedFileTypes::clean_targets)
+        {
+            if targets.iter().any(|(t, _)| t == &target)
+                || !table_exists(&db, target.table()).await?
+            {
+                continue;
+            }
+
+            if let Scope::Window(..) = scope {
+                let sql = format!("SELECT EXISTS (SELECT 1 {})", target.from_unstamped());
+                let unstamped: bool = sqlx::query_scalar(&sql).fetch_one(&db).await?;
+                if unstamped {
+                    anyhow::bail!(
+                        "{} has rows imported before file timestamps were recorded, which --after/--before can't select, delete or backfill them before cleaning by time window",
+                        target.table()
+                    );
+                }
+            }
+
+            let sql = format!("SELECT count(*) {}", scope.clause(&target));
+            let query = sqlx::query_scalar(&sql);
+            let count = match &scope {
+                Scope::Window(after, before) => query.bind(after).bind(before),
+                Scope::File(file_key) => query.bind(file_key),
+            }
+            .fetch_one(&db)
+            .await?;
+            targets.push((target, count));
+        }
+
+        let mut ledger: Vec<(String, i64)> = vec![];
+        if table_exists(&db, "processed_files").await? {
+            match &scope {
+                Scope::Window(after, before) => {
+                    for file_type in &file_types {
+                        let key = file_type.key();
+                        let count = processed_files::count(&db, &key, *after, *before).await?;
+                        ledger.push((key, count));
+                    }
+                }
+                Scope::File(file_key) => {
+                    for file_type in &file_types {
+                        let key = file_type.key();
+                        let count = processed_files::count_file(&db, &key, file_key).await?;
+                        ledger.push((key, count));
+                    }
+                }
+            }
+        }
+
+        match &scope {
+            Scope::Window(after, before) => println!(
+                "rows to delete (after: {}, before: {}):",
+                after.map_or("-".to_string(), |t| t.to_string()),
+                before.map_or("-".to_string(), |t| t.to_string())
+            ),
+            Scope::File(file_key) => println!("rows to delete (file: {}):", file_key),
+        }
+        for (target, count) in &targets {
+            println!("  {}: {}", target.table(), count);
+        }
+        for (key, count) in &ledger {
+            println!("  processed_files ({}): {}", key, count);
+        }
+
+        let total = targets.iter().map(|(_, count)| count).sum::<i64>()
+            + ledger.iter().map(|(_, count)| count).sum::<i64>();
+        if total == 0 {
+            println!("nothing to clean");
+            return Ok(());
+        }
+
+        if !self.yes && !confirm()? {
+            println!("aborted");
+            return Ok(());
+        }
+
+        let mut transaction = db.begin().await?;
+        for (target, _) in &targets {
+            let sql = format!("DELETE {}", scope.clause(target));
+            let query = sqlx::query(&sql);
+            let deleted = match &scope {
+                Scope::Window(after, before) => query.bind(after).bind(before),
+                Scope::File(file_key) => query.bind(file_key),
+            }
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+            println!("deleted {} rows from {}", deleted, target.table());
+        }
+        for (key, _) in &ledger {
+            let deleted = match &scope {
+                Scope::Window(after, before) => {
+                    processed_files::delete(&mut transaction, key, *after, *before).await?
+                }
+                Scope::File(file_key) => {
+                    processed_files::delete_file(&mut transaction, key, file_key).await?
+                }
+            };
+            println!("deleted {} rows from processed_files ({})", deleted, key);
+        }
+        transaction.commit().await?;
+
         Ok(())
     }
 }
+
+// every file type reading the file's prefix, some prefixes are imported by more than one
+fn file_types_for_key(file_key: &str) -> anyhow::Result<Vec<SupportedFileTypes>> {
+    let file_info = FileInfo::from_str(file_key)?;
+    let file_types: Vec<SupportedFileTypes> = SupportedFileTypes::value_variants()
+        .iter()
+        .filter(|file_type| file_type.prefix() == file_info.prefix)
+        .cloned()
+        .collect();
+
+    if file_types.is_empty() {
+        anyhow::bail!("unsupported file type: {}", file_info.prefix);
+    }
+    Ok(file_types)
+}
+
+async fn table_exists(db: &Pool<Postgres>, table: &str) -> anyhow::Result<bool> {
+    sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(table)
+        .fetch_one(db)
+        .await
+        .map_err(anyhow::Error::from)
+}
+
+fn confirm() -> anyhow::Result<bool> {
+    print!("delete these rows? [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(file_type: SupportedFileTypes) -> String {
+        format!("{}.1700000000000.gz", file_type.prefix())
+    }
+
+    #[test]
+    fn file_types_for_key_finds_every_reader() -> anyhow::Result<()> {
+        assert_eq!(
+            file_types_for_key(&key(SupportedFileTypes::ValidatedHeartbeat))?,
+            vec![
+                SupportedFileTypes::ValidatedHeartbeat,
+                SupportedFileTypes::ValidatedWifiHeartbeat
+            ]
+        );
+        assert_eq!(
+            file_types_for_key(&key(SupportedFileTypes::RewardManifest))?,
+            vec![SupportedFileTypes::RewardManifest]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn file_types_for_key_rejects_unknown_files() {
+        assert!(file_types_for_key("not_a_file_type.1700000000000.gz").is_err());
+        assert!(file_types_for_key("not a file key").is_err());
+    }
+}
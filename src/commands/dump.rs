@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use file_store::{file_source, FileInfo};
+use futures::TryStreamExt;
+
+use crate::SupportedFileTypes;
+
+use super::SourceArgs;
+
+#[derive(Debug, clap::Args)]
+pub struct Dump {
+    #[arg(short, long)]
+    file_type: SupportedFileTypes,
+    /// Local path of the file, or its key when --bucket or --dir is given
+    #[arg(long)]
+    file: String,
+    /// Stop after printing this many records
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Only print records that reference this hotspot key
+    #[arg(long)]
+    hotspot_key: Option<String>,
+    /// Pretty-print each record instead of one JSON object per line
+    #[arg(long)]
+    pretty: bool,
+    #[command(flatten)]
+    source: SourceArgs,
+}
+
+impl Dump {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let bytes_stream = if self.source.bucket.is_some() || self.source.dir.is_some() {
+            let file_info = FileInfo::from_str(&self.file)?;
+            self.source
+                .file_source()
+                .await?
+                .stream_file(&file_info)
+                .await?
+        } else {
+            file_source::source([&self.file])
+        };
+
+        let mut batches = self.file_type.decode(bytes_stream, false);
+        let mut printed = 0;
+
+        while let Some(batch) = batches.try_next().await? {
+            if let Some(dead_letter) = batch.undecodable().first() {
+                anyhow::bail!(
+                    "undecodable record at offset {}: {}",
+                    dead_letter.raw.offset,
+                    dead_letter.error
+                );
+            }
+
+            for record in batch.json_records() {
+                if self.limit.is_some_and(|limit| printed >= limit) {
+                    return Ok(());
+                }
+
+                if let Some(hotspot_key) = &self.hotspot_key {
+                    if !references(&record, hotspot_key) {
+                        continue;
+                    }
+                }
+
+                if self.pretty {
+                    println!("{}", serde_json::to_string_pretty(&record)?);
+                } else {
+                    println!("{}", record);
+                }
+                printed += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn references(value: &serde_json::Value, key: &str) -> bool {
+    match value {
+        serde_json::Value::String(s) => s == key,
+        serde_json::Value::Array(values) => values.iter().any(|v| references(v, key)),
+        serde_json::Value::Object(map) => map.values().any(|v| references(v, key)),
+        _ => false,
+    }
+}
@@ -1,47 +1,512 @@
-use crate::SupportedFileTypes;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 
-use super::{DbArgs, S3Args, TimeArgs};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use file_store::FileInfo;
+use futures::{stream, StreamExt, TryStreamExt};
+use sqlx::{Pool, Postgres};
+
+use crate::{
+    indexes, migrations,
+    partitions::{self, PartitionBy},
+    processed_files,
+    record_errors::{self, ErrorPolicy, RecordErrors},
+    SupportedFileTypes,
+};
+
+use super::{DbArgs, FileSource, SourceArgs, TimeArgs};
 
 #[derive(Debug, clap::Args)]
 pub struct Import {
-    #[arg(short, long)]
-    file_type: SupportedFileTypes,
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        required_unless_present_any = ["all_mobile", "all_iot"]
+    )]
+    file_type: Vec<SupportedFileTypes>,
+    /// Import every mobile file type
+    #[arg(long)]
+    all_mobile: bool,
+    /// Import every iot file type
+    #[arg(long)]
+    all_iot: bool,
+    /// Re-import files that are already recorded as processed
+    #[arg(long)]
+    force: bool,
+    /// Number of files to stream, decode and insert at the same time
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    concurrency: u32,
+    /// Decode the files and report what they contain without touching the database
+    #[arg(long, conflicts_with = "force")]
+    dry_run: bool,
+    /// Keep polling for new files after the initial import until interrupted
+    #[arg(long, conflicts_with_all = ["dry_run", "before"])]
+    follow: bool,
+    /// How long to wait between polls in follow mode, e.g. 30s, 5m or 1h
+    #[arg(long, default_value = "5m", value_parser = parse_interval)]
+    interval: Duration,
+    /// What to do with records that cannot be inserted: fail the file, skip them, or skip
+    /// them and keep their raw bytes in the dead_letters table
+    #[arg(long, value_enum, default_value_t = ErrorPolicy::Fail)]
+    on_error: ErrorPolicy,
+    /// Create the file types' indexes after the files are loaded, loading into unindexed
+    /// tables is much faster
+    #[arg(long, conflicts_with = "dry_run")]
+    create_indexes: bool,
+    /// Create high-volume tables that don't exist yet as partitioned tables, with a
+    /// partition per day or per reward epoch added as data arrives
+    #[arg(long, value_enum, conflicts_with = "dry_run")]
+    partition_by: Option<PartitionBy>,
     #[command(flatten)]
-    db: DbArgs,
+    db: ImportDbArgs,
     #[command(flatten)]
-    s3: S3Args,
+    source: SourceArgs,
     #[command(flatten)]
     time: TimeArgs,
 }
 
+// DbArgs, with --db-url only required when the import touches the database
+#[derive(Debug, clap::Args)]
+struct ImportDbArgs {
+    #[arg(short, long, required_unless_present = "dry_run")]
+    db_url: Option<String>,
+    /// Postgres schema to create and read every table in, created if it doesn't exist
+    #[arg(long)]
+    schema: Option<String>,
+}
+
+impl ImportDbArgs {
+    fn db_args(&self) -> anyhow::Result<DbArgs> {
+        let Some(db_url) = self.db_url.clone() else {
+            anyhow::bail!("--db-url is required");
+        };
+
+        Ok(DbArgs {
+            db_url,
+            schema: self.schema.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    files: usize,
+    records: usize,
+    bad_records: usize,
+    failed: usize,
+    // timestamp the next listing for this file type should start after
+    cursor: Option<DateTime<Utc>>,
+}
+
 impl Import {
     pub async fn run(self) -> anyhow::Result<()> {
-        let db = self.db.connect().await?;
-        let store = self.s3.file_store().await?;
-
-        let file_infos = store
-            .list_all(
-                &self.file_type.prefix(),
-                self.time.after_utc(),
-                self.time.before_utc(),
-            )
+        let source = self.source.file_source().await?;
+        let db = if self.dry_run {
+            None
+        } else {
+            // each file in flight holds its import transaction and may need a second
+            // connection to create a partition
+            let db = self
+                .db
+                .db_args()?
+                .connect_with_max_connections(self.concurrency * 2)
+                .await?;
+            processed_files::create_table(&db).await?;
+            partitions::create_table(&db).await?;
+            if self.on_error == ErrorPolicy::Quarantine {
+                record_errors::create_table(&db).await?;
+            }
+            Some(db)
+        };
+
+        let mut cursors: Vec<(SupportedFileTypes, Option<DateTime<Utc>>)> = self
+            .file_types()
+            .into_iter()
+            .map(|file_type| (file_type, self.time.after_utc()))
+            .collect();
+
+        if !self.follow {
+            let started = Instant::now();
+            let summaries = self.run_once(db.as_ref(), &source, &mut cursors).await?;
+            return report(&summaries, started);
+        }
+
+        let ctrl_c = tokio::signal::ctrl_c();
+        tokio::pin!(ctrl_c);
+
+        loop {
+            let started = Instant::now();
+            tokio::select! {
+                result = self.run_once(db.as_ref(), &source, &mut cursors) => {
+                    if let Err(err) = result.and_then(|summaries| report(&summaries, started)) {
+                        println!("import pass failed: {:?}", err);
+                    }
+                }
+                _ = &mut ctrl_c => {
+                    println!("received SIGINT, in-flight files were rolled back, shutting down");
+                    return Ok(());
+                }
+            }
+
+            println!("waiting {:?} for new files", self.interval);
+            tokio::select! {
+                _ = tokio::time::sleep(self.interval) => {}
+                _ = &mut ctrl_c => {
+                    println!("received SIGINT, shutting down");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn run_once(
+        &self,
+        db: Option<&Pool<Postgres>>,
+        source: &FileSource,
+        cursors: &mut [(SupportedFileTypes, Option<DateTime<Utc>>)],
+    ) -> anyhow::Result<Vec<(String, Summary)>> {
+        let mut summaries = vec![];
+        for (file_type, after) in cursors.iter_mut() {
+            let summary = match db {
+                Some(db) => self.import(file_type, db, source, *after).await?,
+                None => self.dry_run(file_type, source, *after).await?,
+            };
+            *after = summary.cursor.or(*after);
+            summaries.push((file_type.key(), summary));
+        }
+
+        if let Some(db) = db.filter(|_| self.create_indexes) {
+            for (file_type, _) in cursors.iter() {
+                for index in indexes::create(db, file_type).await? {
+                    println!("created index {} for {}", index, file_type.key());
+                }
+            }
+        }
+
+        Ok(summaries)
+    }
+
+    fn file_types(&self) -> Vec<SupportedFileTypes> {
+        let groups = SupportedFileTypes::value_variants()
+            .iter()
+            .filter(|file_type| {
+                (self.all_mobile && file_type.is_mobile()) || (self.all_iot && file_type.is_iot())
+            });
+
+        let mut file_types: Vec<SupportedFileTypes> = vec![];
+        for file_type in self.file_type.iter().chain(groups) {
+            if !file_types.contains(file_type) {
+                file_types.push(file_type.clone());
+            }
+        }
+        file_types
+    }
+
+    async fn import(
+        &self,
+        file_type: &SupportedFileTypes,
+        db: &Pool<Postgres>,
+        source: &FileSource,
+        after: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Summary> {
+        let key = file_type.key();
+        let file_infos = source
+            .list_all(&file_type.prefix(), after, self.time.before_utc())
             .await?;
+        let last_listed = file_infos.iter().map(|file_info| file_info.timestamp).max();
 
-        self.file_type.create_table(&db).await?;
+        if let Some(partition_by) = self.partition_by {
+            partitions::create_tables(db, file_type, partition_by).await?;
+        }
+        if let Some(version) = migrations::run(db, file_type).await?.last() {
+            println!("migrated {} to version {}", key, version);
+        }
 
-        for file_info in file_infos {
-            println!("processing file: {}", file_info);
-            let file_timestamp = file_info.timestamp;
+        let processed = if self.force {
+            HashSet::new()
+        } else {
+            processed_files::processed_keys(db, &key).await?
+        };
 
-            let bytes_stream = store.stream_file(file_info).await?;
+        let key = key.as_str();
+        let results: Vec<(FileInfo, anyhow::Result<(usize, usize)>)> = stream::iter(file_infos)
+            .filter(|file_info| {
+                let skip = processed.contains(&file_info.key);
+                if skip {
+                    println!("skipping processed file: {}", file_info);
+                }
+                async move { !skip }
+            })
+            .map(|file_info| async move {
+                let result =
+                    import_file(file_type, db, source, key, &file_info, self.on_error).await;
+                (file_info, result)
+            })
+            .buffer_unordered(self.concurrency as usize)
+            .collect()
+            .await;
 
-            self.file_type
-                .decode(bytes_stream)
-                .await?
-                .insert(&db, file_timestamp)
-                .await?;
+        let mut summary = Summary::default();
+        let mut first_failed: Option<DateTime<Utc>> = None;
+        for (file_info, result) in results {
+            summary.files += 1;
+            match result {
+                Ok((row_count, bad_records)) => {
+                    summary.records += row_count;
+                    summary.bad_records += bad_records;
+                }
+                Err(err) => {
+                    summary.failed += 1;
+                    first_failed = Some(first_failed.map_or(file_info.timestamp, |timestamp| {
+                        timestamp.min(file_info.timestamp)
+                    }));
+                    println!("failed to import file: {}, error: {:?}", file_info, err);
+                }
+            }
         }
 
-        Ok(())
+        // retry failed files on the next poll, processed ones are skipped via the ledger
+        summary.cursor = match first_failed {
+            Some(timestamp) => Some(timestamp - chrono::Duration::milliseconds(1)),
+            None => last_listed,
+        };
+
+        Ok(summary)
+    }
+
+    async fn dry_run(
+        &self,
+        file_type: &SupportedFileTypes,
+        source: &FileSource,
+        after: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Summary> {
+        let file_infos = source
+            .list_all(&file_type.prefix(), after, self.time.before_utc())
+            .await?;
+        let last_listed = file_infos.iter().map(|file_info| file_info.timestamp).max();
+
+        let mut results = stream::iter(file_infos)
+            .map(|file_info| async move {
+                let decoded = decode_file(file_type, source, &file_info).await;
+                (file_info, decoded)
+            })
+            .buffered(self.concurrency as usize);
+
+        let mut summary = Summary {
+            cursor: last_listed,
+            ..Default::default()
+        };
+        while let Some((file_info, decoded)) = results.next().await {
+            summary.files += 1;
+            summary.records += decoded.records;
+
+            let span = decoded
+                .time_span
+                .map(|(first, last)| format!("{} - {}", first, last))
+                .unwrap_or_else(|| "-".to_string());
+
+            match decoded.error {
+                Some(err) => {
+                    summary.failed += 1;
+                    println!(
+                        "file: {}, records: {}, time span: {}, decode error: {:?}",
+                        file_info, decoded.records, span, err
+                    );
+                }
+                None if decoded.undecodable > 0 => {
+                    summary.failed += 1;
+                    summary.bad_records += decoded.undecodable;
+                    println!(
+                        "file: {}, records: {}, time span: {}, undecodable records: {}",
+                        file_info, decoded.records, span, decoded.undecodable
+                    );
+                }
+                None => println!(
+                    "file: {}, records: {}, time span: {}",
+                    file_info, decoded.records, span
+                ),
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+fn report(summaries: &[(String, Summary)], started: Instant) -> anyhow::Result<()> {
+    let elapsed = started.elapsed();
+    let mut total = Summary::default();
+    println!("summary:");
+    for (key, summary) in summaries {
+        println!(
+            "  {}: files: {}, records: {}, bad records: {}, failed: {}",
+            key, summary.files, summary.records, summary.bad_records, summary.failed
+        );
+        total.files += summary.files;
+        total.records += summary.records;
+        total.bad_records += summary.bad_records;
+        total.failed += summary.failed;
+    }
+    println!(
+        "  total: files: {}, records: {}, bad records: {}, failed: {}, elapsed: {:.1}s ({:.0} records/s)",
+        total.files,
+        total.records,
+        total.bad_records,
+        total.failed,
+        elapsed.as_secs_f64(),
+        total.records as f64 / elapsed.as_secs_f64()
+    );
+
+    if total.failed > 0 {
+        anyhow::bail!("{} file(s) failed", total.failed);
+    }
+
+    Ok(())
+}
+
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let (value, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid interval: {}", s))?;
+
+    if value == 0 {
+        return Err(format!("interval must be greater than zero: {}", s));
+    }
+
+    let seconds = match unit {
+        "" | "s" => Some(value),
+        "m" => value.checked_mul(60),
+        "h" => value.checked_mul(60 * 60),
+        _ => {
+            return Err(format!(
+                "invalid interval unit in {}, expected s, m or h",
+                s
+            ))
+        }
+    };
+
+    seconds
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("interval too large: {}", s))
+}
+
+async fn import_file(
+    file_type: &SupportedFileTypes,
+    db: &Pool<Postgres>,
+    source: &FileSource,
+    key: &str,
+    file_info: &FileInfo,
+    on_error: ErrorPolicy,
+) -> anyhow::Result<(usize, usize)> {
+    println!("processing file: {}", file_info);
+    let started = Instant::now();
+    let bytes_stream = source.stream_file(file_info).await?;
+
+    let mut batches = file_type.decode(bytes_stream, on_error == ErrorPolicy::Quarantine);
+    let mut errors = RecordErrors::new(on_error);
+    let mut row_count = 0;
+
+    let mut transaction = db.begin().await?;
+    while let Some(batch) = batches.try_next().await? {
+        partitions::ensure(db, batch.partition_keys(file_info)).await?;
+        row_count += batch
+            .insert(&mut transaction, file_info, &mut errors)
+            .await?;
+    }
+    errors
+        .write_dead_letters(&mut transaction, key, file_info)
+        .await?;
+
+    processed_files::record(&mut transaction, key, file_info, row_count).await?;
+    transaction.commit().await?;
+
+    let elapsed = started.elapsed();
+    println!(
+        "imported file: {}, records: {}, bad records: {}, elapsed: {:.1}s ({:.0} records/s)",
+        file_info,
+        row_count,
+        errors.count(),
+        elapsed.as_secs_f64(),
+        row_count as f64 / elapsed.as_secs_f64()
+    );
+    Ok((row_count, errors.count()))
+}
+
+struct DecodeSummary {
+    records: usize,
+    time_span: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    undecodable: usize,
+    error: Option<anyhow::Error>,
+}
+
+async fn decode_file(
+    file_type: &SupportedFileTypes,
+    source: &FileSource,
+    file_info: &FileInfo,
+) -> DecodeSummary {
+    let mut summary = DecodeSummary {
+        records: 0,
+        time_span: None,
+        undecodable: 0,
+        error: None,
+    };
+
+    let mut batches = match source.stream_file(file_info).await {
+        Ok(bytes_stream) => file_type.decode(bytes_stream, false),
+        Err(err) => {
+            summary.error = Some(err);
+            return summary;
+        }
+    };
+
+    loop {
+        match batches.try_next().await {
+            Ok(Some(batch)) => {
+                summary.records += batch.record_count();
+                summary.undecodable += batch.undecodable().len();
+                if let Some((first, last)) = batch.time_span() {
+                    summary.time_span = Some(match summary.time_span {
+                        Some((f, l)) => (f.min(first), l.max(last)),
+                        None => (first, last),
+                    });
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                summary.error = Some(err);
+                break;
+            }
+        }
+    }
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_interval_units() {
+        assert_eq!(parse_interval("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_interval("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_interval("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_interval("2h"), Ok(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn parse_interval_rejects_bad_input() {
+        assert!(parse_interval("").is_err());
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("10d").is_err());
+        assert!(parse_interval("1.5h").is_err());
+        assert!(parse_interval("-5s").is_err());
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval(&format!("{}h", u64::MAX / 60)).is_err());
     }
 }
@@ -0,0 +1,42 @@
+use clap::ValueEnum;
+
+use crate::{migrations, SupportedFileTypes};
+
+use super::DbArgs;
+
+#[derive(Debug, clap::Args)]
+pub struct Migrate {
+    /// Only migrate this file type's tables instead of every file type
+    #[arg(short, long)]
+    file_type: Option<SupportedFileTypes>,
+    #[command(flatten)]
+    db: DbArgs,
+}
+
+impl Migrate {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let db = self.db.connect().await?;
+
+        let file_types = match &self.file_type {
+            Some(file_type) => vec![file_type.clone()],
+            None => SupportedFileTypes::value_variants().to_vec(),
+        };
+
+        for file_type in file_types {
+            let key = file_type.key();
+            let applied = migrations::run(&db, &file_type).await?;
+            let version = migrations::current_version(&db, &key).await?;
+
+            if applied.is_empty() {
+                println!("{}: up to date at version {}", key, version);
+            } else {
+                println!(
+                    "{}: applied migrations {:?}, now at version {}",
+                    key, applied, version
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,29 +1,64 @@
+use std::{path::PathBuf, str::FromStr};
+
 use chrono::{DateTime, NaiveDateTime, Utc};
-use file_store::FileStore;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use file_store::{file_source, BytesMutStream, FileInfo, FileStore};
+use sqlx::{postgres::PgPoolOptions, Executor, Pool, Postgres};
 
 pub mod animal_names;
 pub mod clean;
+pub mod dump;
 pub mod import;
+pub mod migrate;
 pub mod reward_analyzer;
+pub mod threshold_status;
 
 #[derive(Debug, clap::Args)]
 pub struct DbArgs {
     #[arg(short, long)]
     db_url: String,
+    /// Postgres schema to create and read every table in, created if it doesn't exist
+    #[arg(long)]
+    schema: Option<String>,
 }
 
 impl DbArgs {
     pub async fn connect(&self) -> anyhow::Result<Pool<Postgres>> {
-        let pool = PgPoolOptions::new()
-            .max_connections(1)
-            .connect(&self.db_url)
-            .await?;
+        self.connect_with_max_connections(1).await
+    }
+
+    pub async fn connect_with_max_connections(
+        &self,
+        max_connections: u32,
+    ) -> anyhow::Result<Pool<Postgres>> {
+        let mut options = PgPoolOptions::new().max_connections(max_connections);
+        if let Some(schema) = &self.schema {
+            // only the schema is on the search path, so tables missing from it are never
+            // read from or written to public by accident
+            let search_path = format!("SET search_path TO {}", quote_ident(schema));
+            options = options.after_connect(move |conn, _meta| {
+                let search_path = search_path.clone();
+                Box::pin(async move {
+                    conn.execute(search_path.as_str()).await?;
+                    Ok(())
+                })
+            });
+        }
+
+        let pool = options.connect(&self.db_url).await?;
+
+        if let Some(schema) = &self.schema {
+            pool.execute(format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(schema)).as_str())
+                .await?;
+        }
 
         Ok(pool)
     }
 }
 
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
 #[derive(Debug, clap::Args)]
 pub struct TimeArgs {
     #[arg(long)]
@@ -43,27 +78,90 @@ impl TimeArgs {
 }
 
 #[derive(Debug, clap::Args)]
-pub struct S3Args {
+pub struct SourceArgs {
+    /// Read files from a local directory instead of S3
+    #[arg(long, conflicts_with_all = ["bucket", "endpoint"])]
+    dir: Option<PathBuf>,
     #[arg(short, long)]
-    bucket: String,
+    bucket: Option<String>,
     #[arg(short, long, default_value = "us-west-2")]
     region: String,
     #[arg(short, long)]
     endpoint: Option<String>,
 }
 
-impl S3Args {
-    pub async fn file_store(&self) -> anyhow::Result<FileStore> {
-        FileStore::new(
-            self.bucket.clone(),
-            self.endpoint.clone(),
-            Some(self.region.clone()),
-            None,
-            None,
-            None,
-            None,
-        )
-        .await
-        .map_err(anyhow::Error::from)
+impl SourceArgs {
+    pub async fn file_source(&self) -> anyhow::Result<FileSource> {
+        match (&self.dir, &self.bucket) {
+            (Some(dir), _) => Ok(FileSource::Dir(dir.clone())),
+            (None, Some(bucket)) => FileStore::new(
+                bucket.clone(),
+                self.endpoint.clone(),
+                Some(self.region.clone()),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .map(FileSource::S3)
+            .map_err(anyhow::Error::from),
+            (None, None) => anyhow::bail!("either --bucket or --dir is required"),
+        }
+    }
+}
+
+pub enum FileSource {
+    S3(FileStore),
+    Dir(PathBuf),
+}
+
+impl FileSource {
+    pub async fn list_all(
+        &self,
+        prefix: &str,
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<FileInfo>> {
+        match self {
+            FileSource::S3(store) => store
+                .list_all(prefix, after, before)
+                .await
+                .map_err(anyhow::Error::from),
+            FileSource::Dir(dir) => {
+                let mut file_infos = vec![];
+                let mut entries = tokio::fs::read_dir(dir).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let Some(file_info) = entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| FileInfo::from_str(name).ok())
+                    else {
+                        continue;
+                    };
+
+                    if file_info.prefix == prefix
+                        && after.is_none_or(|after| file_info.timestamp > after)
+                        && before.is_none_or(|before| file_info.timestamp < before)
+                    {
+                        let size = entry.metadata().await?.len() as usize;
+                        file_infos.push(FileInfo { size, ..file_info });
+                    }
+                }
+
+                file_infos.sort_by_key(|file_info| file_info.timestamp);
+                Ok(file_infos)
+            }
+        }
+    }
+
+    pub async fn stream_file(&self, file_info: &FileInfo) -> anyhow::Result<BytesMutStream> {
+        match self {
+            FileSource::S3(store) => store
+                .stream_file(file_info.clone())
+                .await
+                .map_err(anyhow::Error::from),
+            FileSource::Dir(dir) => Ok(file_source::source([dir.join(&file_info.key)])),
+        }
     }
 }
@@ -6,7 +6,7 @@ use coverage_map::{
 };
 use futures::TryStreamExt;
 use helium_crypto::PublicKeyBinary;
-use hex_assignments::assignment::HexAssignments;
+use hex_assignments::assignment::{Assignment, HexAssignments};
 use hextree::Cell;
 use sqlx::{Pool, Postgres};
 
@@ -15,20 +15,26 @@ pub async fn load_coverage_map(
     start_period: DateTime<Utc>,
     end_period: DateTime<Utc>,
 ) -> anyhow::Result<CoverageMap> {
-    let coverage = sqlx::query_as::<_, RadioCoverage>(include_str!("coverage.sql"))
+    let (coverage, unassigned) = sqlx::query_as::<_, RadioCoverage>(include_str!("coverage.sql"))
         .bind(start_period)
         .bind(end_period)
         .fetch(db)
         .try_fold(
-            HashMap::<PublicKeyBinary, CoverageObject>::new(),
-            |mut map, rc| async move {
+            (HashMap::<PublicKeyBinary, CoverageObject>::new(), 0),
+            |(mut map, mut unassigned), rc| async move {
+                // hexes oracle boosting hasn't assigned yet can't be scored
+                let Some(assignments) = rc.assignments() else {
+                    unassigned += 1;
+                    return Ok((map, unassigned));
+                };
+
                 match map.get_mut(&rc.hotspot_key) {
                     Some(co) => {
                         co.coverage.push(UnrankedCoverage {
                             location: rc.hex,
                             signal_power: rc.signal_power,
                             signal_level: rc.signal_level.into(),
-                            assignments: rc.assignments,
+                            assignments,
                         });
                     }
                     None => {
@@ -42,17 +48,26 @@ pub async fn load_coverage_map(
                                     location: rc.hex,
                                     signal_power: rc.signal_power,
                                     signal_level: rc.signal_level.into(),
-                                    assignments: rc.assignments,
+                                    assignments,
                                 }],
                             },
                         );
                     }
                 }
 
-                Ok(map)
+                Ok((map, unassigned))
             },
         )
-        .await?
+        .await?;
+
+    if unassigned > 0 {
+        println!(
+            "skipped {} covered hexes without an oracle boosting assignment, import oracle boosting reports for the period",
+            unassigned
+        );
+    }
+
+    let coverage = coverage
         .into_values()
         .fold(CoverageMapBuilder::default(), |mut builder, co| {
             builder.insert_coverage_object(co);
@@ -73,8 +88,21 @@ pub struct RadioCoverage {
     pub indoor: bool,
     pub signal_level: SignalLevel,
     pub signal_power: i32,
-    #[sqlx(flatten)]
-    pub assignments: HexAssignments,
+    pub footfall: Option<Assignment>,
+    pub landtype: Option<Assignment>,
+    pub urbanized: Option<Assignment>,
+    pub service_provider_override: Option<Assignment>,
+}
+
+impl RadioCoverage {
+    fn assignments(&self) -> Option<HexAssignments> {
+        Some(HexAssignments {
+            footfall: self.footfall?,
+            landtype: self.landtype?,
+            urbanized: self.urbanized?,
+            service_provider_override: self.service_provider_override?,
+        })
+    }
 }
 
 struct NoBoostedHexes;
@@ -36,6 +36,23 @@ seniorities AS (
     ORDER BY
         radio_key,
         inserted_at DESC
+),
+assignments AS (
+    SELECT DISTINCT ON (coverage_object, location)
+        coverage_object,
+        location,
+        urbanized,
+        footfall,
+        landtype,
+        service_provider_override
+    FROM
+        oracle_boosting
+    WHERE
+        coverage_object IN (SELECT coverage_object FROM latest_uuids)
+    ORDER BY
+        coverage_object,
+        location,
+        timestamp DESC
 )
 SELECT
     vr.hotspot_key,
@@ -44,13 +61,15 @@ SELECT
     co.indoor,
     hc.signal_level,
     hc.signal_power,
-    hc.footfall,
-    hc.landtype,
-    hc.urbanized,
-    hc.service_provider_override
+    ob.footfall::oracle_assignment AS footfall,
+    ob.landtype::oracle_assignment AS landtype,
+    ob.urbanized::oracle_assignment AS urbanized,
+    ob.service_provider_override::oracle_assignment AS service_provider_override
 FROM
     valid_radios vr
     INNER JOIN latest_uuids u ON vr.hotspot_key = u.hotspot_key
     INNER JOIN hexes hc ON hc.uuid = u.coverage_object
     INNER JOIN seniorities sn ON sn.radio_key = vr.hotspot_key
     INNER JOIN coverage_objects co ON hc.uuid = co.uuid
+    LEFT JOIN assignments ob ON ob.coverage_object = hc.uuid
+        AND ob.location = hc.hex
@@ -0,0 +1,60 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::FromRow;
+
+use super::DbArgs;
+
+#[derive(Debug, clap::Args)]
+pub struct ThresholdStatus {
+    #[arg(long)]
+    hotspot_key: Option<String>,
+    #[arg(long)]
+    cbsd_id: Option<String>,
+    /// Point in time to evaluate the threshold status at, defaults to now
+    #[arg(long)]
+    at: Option<NaiveDateTime>,
+    #[command(flatten)]
+    db: DbArgs,
+}
+
+#[derive(Debug, FromRow)]
+struct RadioThresholdStatus {
+    hotspot_key: String,
+    cbsd_id: Option<String>,
+    status: String,
+    since: DateTime<Utc>,
+    reason: Option<String>,
+}
+
+impl ThresholdStatus {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let db = self.db.connect().await?;
+        let at = self
+            .at
+            .as_ref()
+            .map(NaiveDateTime::and_utc)
+            .unwrap_or_else(Utc::now);
+
+        // the latest valid grant or invalidation received at or before `at` wins
+        let statuses =
+            sqlx::query_as::<_, RadioThresholdStatus>(include_str!("threshold_status.sql"))
+                .bind(at)
+                .bind(&self.hotspot_key)
+                .bind(&self.cbsd_id)
+                .fetch_all(&db)
+                .await?;
+
+        println!("threshold status at {}:", at);
+        for status in statuses {
+            println!(
+                "  hotspot_key: {}, cbsd_id: {}, status: {}, since: {}, reason: {}",
+                status.hotspot_key,
+                status.cbsd_id.as_deref().unwrap_or("-"),
+                status.status,
+                status.since,
+                status.reason.as_deref().unwrap_or("-")
+            );
+        }
+
+        Ok(())
+    }
+}
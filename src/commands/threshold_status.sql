@@ -0,0 +1,16 @@
+WITH events AS (
+    SELECT hotspot_key, cbsd_id, received_timestamp, 'granted' AS status, NULL AS reason
+    FROM radio_thresholds
+    WHERE validated AND status = 'threshold_report_status_valid'
+    UNION ALL
+    SELECT hotspot_key, cbsd_id, received_timestamp, 'invalidated' AS status, reason
+    FROM invalidated_radio_thresholds
+    WHERE status = 'invalidated_threshold_report_status_valid'
+)
+SELECT DISTINCT ON (hotspot_key, cbsd_id)
+    hotspot_key, cbsd_id, status, received_timestamp AS since, reason
+FROM events
+WHERE received_timestamp <= $1
+    AND ($2::text IS NULL OR hotspot_key = $2)
+    AND ($3::text IS NULL OR cbsd_id = $3)
+ORDER BY hotspot_key, cbsd_id, received_timestamp DESC
@@ -1,30 +1,28 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
-use helium_crypto::PublicKey;
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::{PublicKey, PublicKeyBinary};
 use helium_proto::{
     services::poc_mobile::{coverage_object_req_v1, CoverageObjectV1},
     Message,
 };
-use sqlx::{Pool, Postgres};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 
-use crate::{to_datetime, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    pg_copy,
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeCoverageObject {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeCoverageObject {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(
-                |buf| async move { CoverageObjectV1::decode(buf).map_err(anyhow::Error::from) },
-            )
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, CoverageObjectV1::decode)
     }
 }
 
@@ -45,7 +43,55 @@ impl DbTable for FileTypeCoverageObject {
                     radio_type text not null,
                     uuid text not null,
                     coverage_claim_time timestamptz not null,
-                    indoor bool not null
+                    indoor bool not null,
+                    file_key text
+                )
+            "#,
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query(
+            r#"
+                DO $$ BEGIN
+                    CREATE TYPE signal_level AS ENUM ('none', 'low', 'medium', 'high');
+                EXCEPTION
+                    WHEN duplicate_object THEN null;
+                END $$
+            "#,
+        )
+        .execute(db)
+        .await?;
+
+        // the reward analyzer may point at a verifier database, whose hexes table has a
+        // different layout and would be written to here
+        let foreign_hexes: bool = sqlx::query_scalar(
+            r#"
+                SELECT to_regclass('hexes') IS NOT NULL AND NOT EXISTS (
+                    SELECT 1 FROM information_schema.columns
+                    WHERE table_schema = current_schema()
+                        AND table_name = 'hexes'
+                        AND column_name = 'file_key'
+                )
+            "#,
+        )
+        .fetch_one(db)
+        .await?;
+        if foreign_hexes {
+            anyhow::bail!(
+                "hexes already exists and was not created by this tool, rename or drop it before importing coverage objects"
+            );
+        }
+
+        sqlx::query(
+            r#"
+                CREATE TABLE IF NOT EXISTS hexes (
+                    uuid text not null,
+                    hex bigint not null,
+                    signal_level signal_level not null,
+                    signal_power integer not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -54,40 +100,159 @@ impl DbTable for FileTypeCoverageObject {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![
+            CleanTarget::Table {
+                table: "coverage_objects",
+            },
+            CleanTarget::Table { table: "hexes" },
+        ]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["coverage_objects"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![
+            Index {
+                name: "coverage_objects_uuid_idx",
+                table: "coverage_objects",
+                columns: "uuid",
+            },
+            Index {
+                name: "coverage_objects_radio_key_idx",
+                table: "coverage_objects",
+                columns: "radio_key, file_timestamp",
+            },
+            Index {
+                name: "hexes_uuid_idx",
+                table: "hexes",
+                columns: "uuid",
+            },
+        ]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<CoverageObjectV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        for object in self {
-            let co = object.coverage_object.clone().unwrap();
-            let (radio_key, radio_type) = match co.key_type.unwrap() {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let rows = errors.rows(self, |object| {
+            let co = object
+                .coverage_object
+                .as_ref()
+                .ok_or(RecordError::MissingField("coverage_object"))?;
+            let (radio_key, radio_type) = match co
+                .key_type
+                .clone()
+                .ok_or(RecordError::MissingField("key_type"))?
+            {
                 coverage_object_req_v1::KeyType::CbsdId(cbsd_id) => (cbsd_id, "cbrs"),
-                coverage_object_req_v1::KeyType::HotspotKey(bytes) => {
-                    (PublicKey::try_from(bytes)?.to_string(), "wifi")
-                }
+                coverage_object_req_v1::KeyType::HotspotKey(bytes) => (
+                    PublicKey::try_from(bytes)
+                        .map_err(RecordError::invalid("hotspot_key"))?
+                        .to_string(),
+                    "wifi",
+                ),
             };
 
-            let uuid = uuid::Uuid::from_slice(&co.uuid)?;
-            sqlx::query(
-                r#"
-                INSERT INTO coverage_objects(file_timestamp,radio_key, radio_type, uuid, coverage_claim_time, indoor)
-                VALUES ($1,$2,$3,$4,$5,$6)
-                "#,
-            )
-            .bind(file_timestamp)
-            .bind(radio_key)
-            .bind(radio_type)
-            .bind(uuid)
-            .bind(to_datetime(co.coverage_claim_time))
-            .bind(co.indoor)
-            .execute(db)
-            .await?;
-        }
-        Ok(())
+            let uuid = uuid::Uuid::from_slice(&co.uuid).map_err(RecordError::invalid("uuid"))?;
+            let coverage_claim_time = to_datetime(co.coverage_claim_time)?;
+            let hexes = co
+                .coverage
+                .iter()
+                .map(|hex| {
+                    let location = i64::from_str_radix(&hex.location, 16)
+                        .map_err(RecordError::invalid("coverage.location"))?;
+                    Ok((location, hex))
+                })
+                .collect::<Result<Vec<_>, RecordError>>()?;
+            Ok((co, radio_key, radio_type, uuid, coverage_claim_time, hexes))
+        })?;
+
+        let hexes: Vec<_> = rows
+            .iter()
+            .flat_map(|(_, _, _, uuid, _, hexes)| hexes.iter().map(move |hex| (uuid, hex)))
+            .collect();
+        pg_copy::copy_in(
+            transaction,
+            "hexes(uuid, hex, signal_level, signal_power, file_key, file_timestamp)",
+            hexes,
+            |mut row, (uuid, (location, hex))| {
+                row.push(uuid.to_string())
+                    .push(*location)
+                    .push(hex.signal_level().as_str_name().to_lowercase())
+                    .push(hex.signal_power)
+                    .push(&file_info.key)
+                    .push(file_info.timestamp);
+                Ok(())
+            },
+        )
+        .await?;
+
+        let written = rows.len();
+        pg_copy::copy_in(
+            transaction,
+            "coverage_objects(file_timestamp, radio_key, radio_type, uuid, coverage_claim_time, indoor, file_key)",
+            rows,
+            |mut row, (co, radio_key, radio_type, uuid, coverage_claim_time, _)| {
+                row.push(file_info.timestamp)
+                    .push(radio_key)
+                    .push(radio_type)
+                    .push(uuid)
+                    .push(coverage_claim_time)
+                    .push(co.indoor)
+                    .push(&file_info.key);
+                Ok(())
+            },
+        )
+        .await?;
+
+        Ok(written)
+    }
+}
+
+impl Record for CoverageObjectV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.coverage_object
+            .as_ref()
+            .and_then(|co| to_datetime(co.coverage_claim_time).ok())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let Some(co) = self.coverage_object.as_ref() else {
+            return json!({ "validity": self.validity().as_str_name() });
+        };
+
+        let (radio_key, radio_type) = match &co.key_type {
+            Some(coverage_object_req_v1::KeyType::CbsdId(cbsd_id)) => (cbsd_id.clone(), "cbrs"),
+            Some(coverage_object_req_v1::KeyType::HotspotKey(bytes)) => {
+                (PublicKeyBinary::from(bytes.clone()).to_string(), "wifi")
+            }
+            None => (String::new(), "unknown"),
+        };
+
+        json!({
+            "validity": self.validity().as_str_name(),
+            "radio_key": radio_key,
+            "radio_type": radio_type,
+            "uuid": uuid::Uuid::from_slice(&co.uuid).ok(),
+            "coverage_claim_time": to_datetime(co.coverage_claim_time).ok(),
+            "indoor": co.indoor,
+            "coverage": co.coverage.iter().map(|hex| json!({
+                "location": hex.location,
+                "signal_level": hex.signal_level().as_str_name(),
+                "signal_power": hex.signal_power,
+            })).collect::<Vec<_>>(),
+        })
     }
 }
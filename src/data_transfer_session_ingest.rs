@@ -1,27 +1,25 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{services::poc_mobile::DataTransferSessionIngestReportV1, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{to_datetime, to_datetime_ms, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, to_datetime_ms, CleanTarget, DbTable, Decode, Insertable, InsertableStream,
+    Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeDataTransferSessionIngestReport;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeDataTransferSessionIngestReport {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                DataTransferSessionIngestReportV1::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, DataTransferSessionIngestReportV1::decode)
     }
 }
 
@@ -45,7 +43,9 @@ impl DbTable for FileTypeDataTransferSessionIngestReport {
                     download_bytes bigint not null,
                     rewardable_bytes bigint not null,
                     reward_cancelled bool not null,
-                    event_id text not null
+                    event_id text not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -54,38 +54,100 @@ impl DbTable for FileTypeDataTransferSessionIngestReport {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "data_transfer_session_ingest_reports",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["data_transfer_session_ingest_reports"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "data_transfer_session_ingest_reports_pub_key_idx",
+            table: "data_transfer_session_ingest_reports",
+            columns: "pub_key, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<DataTransferSessionIngestReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 9) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO data_transfer_session_ingest_reports(pub_key, received_timestamp, timestamp, payer, upload_bytes, download_bytes, rewardable_bytes, reward_cancelled, event_id)")
-            .push_values(chunk, |mut b, report| {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 11) as usize;
 
-                let req = report.clone().report.unwrap();
-                let usage = req.data_transfer_usage.unwrap();
+        let rows = errors.rows(self, |report| {
+            let req = report
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let usage = req
+                .data_transfer_usage
+                .as_ref()
+                .ok_or(RecordError::MissingField("data_transfer_usage"))?;
+            Ok((
+                req,
+                usage,
+                to_datetime_ms(report.received_timestamp)?,
+                to_datetime(usage.timestamp)?,
+            ))
+        })?;
 
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO data_transfer_session_ingest_reports(pub_key, received_timestamp, timestamp, payer, upload_bytes, download_bytes, rewardable_bytes, reward_cancelled, event_id, file_key, file_timestamp)")
+            .push_values(chunk, |mut b, (req, usage, received_timestamp, timestamp)| {
                 b.push_bind(PublicKeyBinary::from(usage.pub_key.clone()).to_string())
-                    .push_bind(to_datetime_ms(report.received_timestamp))
-                    .push_bind(to_datetime(usage.timestamp))
+                    .push_bind(*received_timestamp)
+                    .push_bind(*timestamp)
                     .push_bind(PublicKeyBinary::from(usage.payer.clone()).to_string())
                     .push_bind(usage.upload_bytes as i64)
                     .push_bind(usage.download_bytes as i64)
                     .push_bind(req.rewardable_bytes as i64)
                     .push_bind(req.reward_cancelled)
-                    .push_bind(usage.event_id);
+                    .push_bind(usage.event_id.clone())
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
-        Ok(())
+        Ok(written as usize)
+    }
+}
+
+impl Record for DataTransferSessionIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime_ms(self.received_timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let req = self.report.as_ref();
+        let usage = req.and_then(|r| r.data_transfer_usage.as_ref());
+
+        json!({
+            "received_timestamp": to_datetime_ms(self.received_timestamp).ok(),
+            "pub_key": usage.map(|u| PublicKeyBinary::from(u.pub_key.clone()).to_string()),
+            "timestamp": usage.and_then(|u| to_datetime(u.timestamp).ok()),
+            "payer": usage.map(|u| PublicKeyBinary::from(u.payer.clone()).to_string()),
+            "upload_bytes": usage.map(|u| u.upload_bytes),
+            "download_bytes": usage.map(|u| u.download_bytes),
+            "rewardable_bytes": req.map(|r| r.rewardable_bytes),
+            "reward_cancelled": req.map(|r| r.reward_cancelled),
+            "event_id": usage.map(|u| &u.event_id),
+        })
     }
 }
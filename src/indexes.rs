@@ -0,0 +1,43 @@
+use sqlx::{Pool, Postgres};
+
+use crate::SupportedFileTypes;
+
+// An index the analysis queries rely on. `create_table` leaves these out so bulk loads
+// don't pay for index maintenance on every insert; `create` builds them afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Index {
+    pub name: &'static str,
+    pub table: &'static str,
+    pub columns: &'static str,
+}
+
+async fn exists(db: &Pool<Postgres>, name: &str) -> anyhow::Result<bool> {
+    sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(name)
+        .fetch_one(db)
+        .await
+        .map_err(anyhow::Error::from)
+}
+
+// Builds any of the file type's indexes that are missing, returning the names of the ones created.
+pub async fn create(
+    db: &Pool<Postgres>,
+    file_type: &SupportedFileTypes,
+) -> anyhow::Result<Vec<&'static str>> {
+    let mut created = vec![];
+    for index in file_type.indexes() {
+        if exists(db, index.name).await? {
+            continue;
+        }
+
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+            index.name, index.table, index.columns
+        ))
+        .execute(db)
+        .await?;
+        created.push(index.name);
+    }
+
+    Ok(created)
+}
@@ -1,28 +1,27 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
-use helium_crypto::PublicKey;
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::{PublicKey, PublicKeyBinary};
 use helium_proto::{
     services::poc_lora::{iot_reward_share, IotRewardShare},
     Message,
 };
-use sqlx::{Pool, Postgres};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 
-use crate::{to_datetime, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Debug, Clone)]
 pub struct FileTypeIotRewardShare {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeIotRewardShare {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move { IotRewardShare::decode(buf).map_err(anyhow::Error::from) })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, IotRewardShare::decode)
     }
 }
 
@@ -43,7 +42,9 @@ impl DbTable for FileTypeIotRewardShare {
                     witness_amount bigint not null,
                     dc_transfer_amount bigint not null,
                     start_period timestamptz not null,
-                    end_period timestamptz not null
+                    end_period timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -56,7 +57,9 @@ impl DbTable for FileTypeIotRewardShare {
                     reward_type text not null,
                     amount bigint not null,
                     start_period timestamptz not null,
-                    end_period timestamptz not null
+                    end_period timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -65,6 +68,32 @@ impl DbTable for FileTypeIotRewardShare {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![
+            CleanTarget::Table {
+                table: "iot_gateway_rewards",
+            },
+            CleanTarget::Table {
+                table: "iot_other_rewards",
+            },
+        ]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["iot_gateway_rewards", "iot_other_rewards"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "iot_gateway_rewards_hotspot_key_idx",
+            table: "iot_gateway_rewards",
+            columns: "hotspot_key, end_period",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
@@ -72,55 +101,122 @@ impl Insertable for Vec<IotRewardShare> {
     #[allow(deprecated)]
     async fn insert(
         &self,
-        pool: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        for share in self {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let mut written = 0;
+        for (index, share) in self.iter().enumerate() {
+            let periods = to_datetime(share.start_period)
+                .and_then(|start_period| Ok((start_period, to_datetime(share.end_period)?)));
+            let (start_period, end_period) = match periods {
+                Ok(periods) => periods,
+                Err(err) => {
+                    errors.push(index, err)?;
+                    continue;
+                }
+            };
+
             match &share.reward {
-            Some(iot_reward_share::Reward::GatewayReward(gateway)) => sqlx::query(
+                Some(iot_reward_share::Reward::GatewayReward(gateway)) => {
+                    let hotspot_key = match PublicKey::try_from(gateway.hotspot_key.clone()) {
+                        Ok(hotspot_key) => hotspot_key,
+                        Err(err) => {
+                            errors.push(
+                                index,
+                                RecordError::InvalidField("hotspot_key", err.to_string()),
+                            )?;
+                            continue;
+                        }
+                    };
+
+                    sqlx::query(
                 r#"
-                    INSERT INTO iot_gateway_rewards(hotspot_key, beacon_amount, witness_amount, dc_transfer_amount, start_period, end_period)
-                    VALUES($1,$2,$3,$4,$5,$6)
+                    INSERT INTO iot_gateway_rewards(hotspot_key, beacon_amount, witness_amount, dc_transfer_amount, start_period, end_period, file_key, file_timestamp)
+                    VALUES($1,$2,$3,$4,$5,$6,$7,$8)
                 "#
                 )
-                .bind(PublicKey::try_from(gateway.hotspot_key.clone())?.to_string())
+                .bind(hotspot_key.to_string())
                 .bind(gateway.beacon_amount as i64)
                 .bind(gateway.witness_amount as i64)
                 .bind(gateway.dc_transfer_amount as i64)
-                .bind(to_datetime(share.start_period))
-                .bind(to_datetime(share.end_period))
-                .execute(pool)
+                .bind(start_period)
+                .bind(end_period)
+                .bind(&file_info.key)
+                .bind(file_info.timestamp)
+                .execute(&mut *transaction)
                 .await
-                .map(|_| ())?,
-            Some(iot_reward_share::Reward::OperationalReward(operational)) => sqlx::query(
-                r#"
-                    INSERT INTO iot_other_rewards(reward_type, amount, start_period, end_period)
-                    VALUES($1,$2,$3,$4)
-                "#
+                .map(|_| ())?
+                }
+                Some(iot_reward_share::Reward::OperationalReward(operational)) => sqlx::query(
+                    r#"
+                    INSERT INTO iot_other_rewards(reward_type, amount, start_period, end_period, file_key, file_timestamp)
+                    VALUES($1,$2,$3,$4,$5,$6)
+                "#,
                 )
                 .bind("operational")
                 .bind(operational.amount as i64)
-                .bind(to_datetime(share.start_period))
-                .bind(to_datetime(share.end_period))
-                .execute(pool)
+                .bind(start_period)
+                .bind(end_period)
+                .bind(&file_info.key)
+                .bind(file_info.timestamp)
+                .execute(&mut *transaction)
                 .await
                 .map(|_| ())?,
-            Some(iot_reward_share::Reward::UnallocatedReward(unallocated)) => sqlx::query(
-                r#"
-                    INSERT INTO iot_other_rewards(reward_type, amount, start_period, end_period)
-                    VALUES($1,$2,$3,$4)
-                "#
+                Some(iot_reward_share::Reward::UnallocatedReward(unallocated)) => sqlx::query(
+                    r#"
+                    INSERT INTO iot_other_rewards(reward_type, amount, start_period, end_period, file_key, file_timestamp)
+                    VALUES($1,$2,$3,$4,$5,$6)
+                "#,
                 )
                 .bind(unallocated.reward_type().as_str_name())
                 .bind(unallocated.amount as i64)
-                .bind(to_datetime(share.start_period))
-                .bind(to_datetime(share.end_period))
-                .execute(pool)
+                .bind(start_period)
+                .bind(end_period)
+                .bind(&file_info.key)
+                .bind(file_info.timestamp)
+                .execute(&mut *transaction)
                 .await
                 .map(|_| ())?,
-            _ => (),
-        };
+                _ => continue,
+            };
+            written += 1;
         }
-        Ok(())
+        Ok(written)
+    }
+}
+
+impl Record for IotRewardShare {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime(self.end_period).ok()
+    }
+
+    #[allow(deprecated)]
+    fn to_json(&self) -> serde_json::Value {
+        let reward = match &self.reward {
+            Some(iot_reward_share::Reward::GatewayReward(gateway)) => json!({
+                "type": "gateway",
+                "hotspot_key": PublicKeyBinary::from(gateway.hotspot_key.clone()).to_string(),
+                "beacon_amount": gateway.beacon_amount,
+                "witness_amount": gateway.witness_amount,
+                "dc_transfer_amount": gateway.dc_transfer_amount,
+            }),
+            Some(iot_reward_share::Reward::OperationalReward(operational)) => json!({
+                "type": "operational",
+                "amount": operational.amount,
+            }),
+            Some(iot_reward_share::Reward::UnallocatedReward(unallocated)) => json!({
+                "type": "unallocated",
+                "reward_type": unallocated.reward_type().as_str_name(),
+                "amount": unallocated.amount,
+            }),
+            None => serde_json::Value::Null,
+        };
+
+        json!({
+            "start_period": to_datetime(self.start_period).ok(),
+            "end_period": to_datetime(self.end_period).ok(),
+            "reward": reward,
+        })
     }
 }
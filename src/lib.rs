@@ -1,25 +1,36 @@
+use std::collections::BTreeSet;
+
 use boosted_hex_update::FileTypeBoostedHexUpdate;
+use bytes::{Bytes, BytesMut};
 use cell_speedtest_ingest::FileTypeCellSpeedtestIngestReport;
 use chrono::{DateTime, TimeZone, Utc};
 use clap::ValueEnum;
 use coverage_object::FileTypeCoverageObject;
 use data_transfer_session_ingest::FileTypeDataTransferSessionIngestReport;
-use file_store::BytesMutStream;
+use file_store::{BytesMutStream, FileInfo};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use indexes::Index;
 use iot_reward_share::FileTypeIotRewardShare;
+use migrations::Migration;
 use mobile_reward_share::FileTypeMobileRewardShare;
 use oracle_boosting::FileTypeOracleBoostingReport;
+use partitions::Partitioned;
 use radio_thresholds::{FileTypeInvalidatedRadioThreshold, FileTypeRadioThreshold};
 use radio_usage_stats_ingest_report::FileTypeRadioUsageStatsIngestReport;
+use record_errors::{DeadLetter, RawRecord, RecordError, RecordErrors};
 use reward_manifest::FileTypeRewardManifest;
 use seniority_update::FileTypeSeniorityUpdate;
 use service_provider_bans::FileTypeServiceProviderBan;
-use sqlx::{Pool, Postgres};
+use sqlx::{Pool, Postgres, Transaction};
 use subscriber_mapping_activity_ingest::FileTypeSubscriberMappingActivityIngest;
 use valid_data_transfer_session::FileTypeValidDataTransferSession;
 // use validated_event_req_v1::FileTypeValidatedEventReq;
 use validated_heartbeat::FileTypeValidatedHeartbeat;
+use validated_wifi_heartbeat::FileTypeValidatedWifiHeartbeat;
 use verified_data_transfer_ingest::FileTypeVerifiedDataTransferIngest;
+use verified_speedtest::FileTypeVerifiedSpeedtest;
 use verified_subscriber_mapping_activity::FileTypeVerifiedSubscriberMappingActivityReport;
+use verified_unique_connections::FileTypeVerifiedUniqueConnections;
 use wifi_heartbeat_ingest_report::FileTypeWifiHeartbeatIngestReport;
 
 mod boosted_hex_update;
@@ -27,11 +38,17 @@ mod cell_speedtest_ingest;
 pub mod commands;
 mod coverage_object;
 mod data_transfer_session_ingest;
+pub mod indexes;
 mod iot_reward_share;
+pub mod migrations;
 mod mobile_reward_share;
 mod oracle_boosting;
+pub mod partitions;
+mod pg_copy;
+mod processed_files;
 mod radio_thresholds;
 mod radio_usage_stats_ingest_report;
+pub mod record_errors;
 mod reward_manifest;
 mod seniority_update;
 mod service_provider_bans;
@@ -40,10 +57,13 @@ mod valid_data_transfer_session;
 mod verified_subscriber_mapping_activity;
 // mod validated_event_req_v1;
 mod validated_heartbeat;
+mod validated_wifi_heartbeat;
 mod verified_data_transfer_ingest;
+mod verified_speedtest;
+mod verified_unique_connections;
 mod wifi_heartbeat_ingest_report;
 
-#[derive(Debug, Clone, ValueEnum)]
+#[derive(Debug, Clone, PartialEq, Eq, ValueEnum)]
 pub enum SupportedFileTypes {
     BoostedHexUpdate,
     CellSpeedtestIngestReport,
@@ -59,9 +79,12 @@ pub enum SupportedFileTypes {
     ServiceProviderBans,
     SubscriberMappingActivityIngest,
     ValidatedHeartbeat,
+    ValidatedWifiHeartbeat,
     ValidDataTransferSession,
     // ValidatedEventReq,
     VerifiedDataTransferIngest,
+    VerifiedSpeedtest,
+    VerifiedUniqueConnections,
     WifiHeartbeatIngestReport,
     RewardManifest,
     VerifiedSubscriberMappingActivityReport,
@@ -101,10 +124,15 @@ impl SupportedFileTypes {
                 Box::new(FileTypeValidDataTransferSession)
             }
             SupportedFileTypes::ValidatedHeartbeat => Box::new(FileTypeValidatedHeartbeat {}),
+            SupportedFileTypes::ValidatedWifiHeartbeat => Box::new(FileTypeValidatedWifiHeartbeat),
             // SupportedFileTypes::ValidatedEventReq => Box::new(FileTypeValidatedEventReq),
             SupportedFileTypes::VerifiedDataTransferIngest => {
                 Box::new(FileTypeVerifiedDataTransferIngest)
             }
+            SupportedFileTypes::VerifiedSpeedtest => Box::new(FileTypeVerifiedSpeedtest),
+            SupportedFileTypes::VerifiedUniqueConnections => {
+                Box::new(FileTypeVerifiedUniqueConnections)
+            }
             SupportedFileTypes::VerifiedSubscriberMappingActivityReport => {
                 Box::new(FileTypeVerifiedSubscriberMappingActivityReport)
             }
@@ -118,27 +146,214 @@ impl SupportedFileTypes {
         self.inner().to_prefix()
     }
 
-    pub async fn decode(&self, buf: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        self.inner().decode(buf).await
+    // Identifies the file type in processed_files, schema_version and dead_letters. Same as
+    // the prefix except for file types that read another file type's files.
+    pub fn key(&self) -> String {
+        match self {
+            SupportedFileTypes::ValidatedWifiHeartbeat => "validated_wifi_heartbeat".to_string(),
+            _ => self.prefix(),
+        }
+    }
+
+    pub fn is_mobile(&self) -> bool {
+        !matches!(self, SupportedFileTypes::IotRewardShare)
+    }
+
+    pub fn is_iot(&self) -> bool {
+        matches!(
+            self,
+            SupportedFileTypes::IotRewardShare | SupportedFileTypes::RewardManifest
+        )
+    }
+
+    // `keep_raw` holds on to each record's bytes for dead_letters, only needed when quarantining
+    pub fn decode(&self, buf: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        self.inner().decode(buf, keep_raw)
     }
 
     pub async fn create_table(&self, db: &Pool<Postgres>) -> anyhow::Result<()> {
         self.inner().create_table(db).await
     }
+
+    pub fn clean_targets(&self) -> Vec<CleanTarget> {
+        self.inner().clean_targets()
+    }
+
+    pub fn migrations(&self) -> Vec<Migration> {
+        self.inner().migrations()
+    }
+
+    pub fn indexes(&self) -> Vec<Index> {
+        self.inner().indexes()
+    }
+
+    pub fn partitioned(&self) -> Vec<Partitioned> {
+        self.inner().partitioned()
+    }
 }
 
-#[async_trait::async_trait]
+pub type InsertableStream = BoxStream<'static, anyhow::Result<Box<dyn Insertable>>>;
+
 pub trait Decode {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>>;
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream;
+}
+
+const DECODE_BATCH_SIZE: usize = 10_000;
+
+fn decode_batches<T, E>(
+    stream: BytesMutStream,
+    keep_raw: bool,
+    decode: fn(BytesMut) -> Result<T, E>,
+) -> InsertableStream
+where
+    T: Record + Send + Sync + 'static,
+    Batch<T>: Insertable,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut offset = 0;
+    stream
+        .map_err(anyhow::Error::from)
+        .map_ok(move |buf| {
+            let raw = RawRecord {
+                offset,
+                bytes: if keep_raw {
+                    buf.clone().freeze()
+                } else {
+                    Bytes::new()
+                },
+            };
+            // every record in a file_store file is prefixed with a 4 byte length
+            offset += 4 + buf.len() as u64;
+            (raw, decode(buf).map_err(|err| err.to_string()))
+        })
+        .try_chunks(DECODE_BATCH_SIZE)
+        .map_err(|err| err.1)
+        .map_ok(|chunk| Box::new(Batch::from(chunk)) as Box<dyn Insertable>)
+        .boxed()
+}
+
+// Decoded records along with the raw bytes they were decoded from, so records
+// that cannot be decoded or inserted can be written to dead_letters.
+struct Batch<T> {
+    records: Vec<T>,
+    raw: Vec<RawRecord>,
+    undecodable: Vec<DeadLetter>,
+}
+
+impl<T> From<Vec<(RawRecord, Result<T, String>)>> for Batch<T> {
+    fn from(chunk: Vec<(RawRecord, Result<T, String>)>) -> Self {
+        let mut batch = Batch {
+            records: Vec::with_capacity(chunk.len()),
+            raw: Vec::with_capacity(chunk.len()),
+            undecodable: vec![],
+        };
+
+        for (raw, decoded) in chunk {
+            match decoded {
+                Ok(record) => {
+                    batch.records.push(record);
+                    batch.raw.push(raw);
+                }
+                Err(error) => batch.undecodable.push(DeadLetter { raw, error }),
+            }
+        }
+
+        batch
+    }
+}
+
+impl<T: Record> Records for Batch<T> {
+    fn record_count(&self) -> usize {
+        self.records.len() + self.undecodable.len()
+    }
+
+    fn time_span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        self.records.time_span()
+    }
+
+    fn json_records(&self) -> Vec<serde_json::Value> {
+        self.records.json_records()
+    }
+
+    fn undecodable(&self) -> &[DeadLetter] {
+        &self.undecodable
+    }
 }
 
 #[async_trait::async_trait]
-pub trait Insertable {
+impl<T> Insertable for Batch<T>
+where
+    T: Record + Send + Sync,
+    Vec<T>: Insertable,
+{
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()>;
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        for dead_letter in &self.undecodable {
+            errors.undecodable(dead_letter)?;
+        }
+
+        let written = self.records.insert(transaction, file_info, errors).await?;
+        errors.dead_letter_pending(&self.raw);
+        Ok(written)
+    }
+
+    fn partition_keys(&self, file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+        // many rows share a timestamp, partitions only need each distinct one once
+        let keys: BTreeSet<_> = self.records.partition_keys(file_info).into_iter().collect();
+        keys.into_iter().collect()
+    }
+}
+
+pub trait Record {
+    fn timestamp(&self) -> Option<DateTime<Utc>>;
+    fn to_json(&self) -> serde_json::Value;
+}
+
+pub trait Records {
+    fn record_count(&self) -> usize;
+    fn time_span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)>;
+    fn json_records(&self) -> Vec<serde_json::Value>;
+
+    fn undecodable(&self) -> &[DeadLetter] {
+        &[]
+    }
+}
+
+impl<T: Record> Records for Vec<T> {
+    fn record_count(&self) -> usize {
+        self.len()
+    }
+
+    fn time_span(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let timestamps = self.iter().filter_map(Record::timestamp);
+        let first = timestamps.clone().min()?;
+        let last = timestamps.max()?;
+        Some((first, last))
+    }
+
+    fn json_records(&self) -> Vec<serde_json::Value> {
+        self.iter().map(Record::to_json).collect()
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Insertable: Records + Send + Sync {
+    // returns how many records were written, leaving out ones filtered out or rejected
+    async fn insert(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize>;
+
+    // (table, partition column value) for every row headed to a partitionable table
+    fn partition_keys(&self, _file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+        vec![]
+    }
 }
 
 pub trait ToPrefix {
@@ -149,17 +364,95 @@ pub trait ToPrefix {
 pub trait DbTable {
     async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()>;
     // async fn drop_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()>;
+    fn clean_targets(&self) -> Vec<CleanTarget>;
+    fn migrations(&self) -> Vec<Migration> {
+        vec![]
+    }
+    fn indexes(&self) -> Vec<Index> {
+        vec![]
+    }
+    fn partitioned(&self) -> Vec<Partitioned> {
+        vec![]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CleanTarget {
+    Table {
+        table: &'static str,
+    },
+    FilteredTable {
+        table: &'static str,
+        condition: &'static str,
+    },
+    ChildTable {
+        table: &'static str,
+        parent: &'static str,
+    },
+}
+
+impl CleanTarget {
+    pub fn table(&self) -> &'static str {
+        match self {
+            CleanTarget::Table { table, .. }
+            | CleanTarget::FilteredTable { table, .. }
+            | CleanTarget::ChildTable { table, .. } => table,
+        }
+    }
+
+    // FROM/WHERE clause selecting the target rows, with $1 = after and $2 = before. The window
+    // is on file_timestamp, the same as processed_files, so a clean drops whole files.
+    pub fn from_where(&self) -> String {
+        const WINDOW: &str = "($1::timestamptz IS NULL OR file_timestamp >= $1) AND ($2::timestamptz IS NULL OR file_timestamp < $2)";
+
+        self.filtered(WINDOW)
+    }
+
+    // FROM/WHERE clause selecting the rows imported before file timestamps were recorded,
+    // which no window selects
+    pub fn from_unstamped(&self) -> String {
+        self.filtered("file_timestamp IS NULL")
+    }
+
+    fn filtered(&self, filter: &str) -> String {
+        match self {
+            CleanTarget::Table { table } => format!("FROM {} WHERE {}", table, filter),
+            CleanTarget::FilteredTable { table, condition } => {
+                format!("FROM {} WHERE {} AND {}", table, condition, filter)
+            }
+            CleanTarget::ChildTable { table, parent } => format!(
+                "FROM {} WHERE id IN (SELECT id FROM {} WHERE {})",
+                table, parent, filter
+            ),
+        }
+    }
+
+    // FROM/WHERE clause selecting the rows imported from a single file, with $1 = file key
+    pub fn from_file_key(&self) -> String {
+        match self {
+            CleanTarget::FilteredTable {
+                table, condition, ..
+            } => format!("FROM {} WHERE {} AND file_key = $1", table, condition),
+            CleanTarget::Table { table, .. } | CleanTarget::ChildTable { table, .. } => {
+                format!("FROM {} WHERE file_key = $1", table)
+            }
+        }
+    }
 }
 
-pub fn to_datetime(timestamp: u64) -> DateTime<Utc> {
-    Utc.timestamp_opt(timestamp as i64, 0).single().unwrap()
+pub fn to_datetime(timestamp: u64) -> Result<DateTime<Utc>, RecordError> {
+    Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .ok_or(RecordError::InvalidTimestamp(timestamp))
 }
 
-pub fn to_datetime_ms(timestamp: u64) -> DateTime<Utc> {
-    Utc.timestamp_millis_opt(timestamp as i64).single().unwrap()
+pub fn to_datetime_ms(timestamp: u64) -> Result<DateTime<Utc>, RecordError> {
+    Utc.timestamp_millis_opt(timestamp as i64)
+        .single()
+        .ok_or(RecordError::InvalidTimestamp(timestamp))
 }
 
-pub fn determine_timestamp(timestamp: u64) -> DateTime<Utc> {
+pub fn determine_timestamp(timestamp: u64) -> Result<DateTime<Utc>, RecordError> {
     const MILLISECOND_THRESHOLD: u64 = 1_000_000_000_000;
 
     if timestamp > MILLISECOND_THRESHOLD {
@@ -171,11 +464,11 @@ pub fn determine_timestamp(timestamp: u64) -> DateTime<Utc> {
     }
 }
 
-pub fn to_optional_datetime(timestamp: u64) -> Option<DateTime<Utc>> {
+pub fn to_optional_datetime(timestamp: u64) -> Result<Option<DateTime<Utc>>, RecordError> {
     if timestamp == 0 {
-        None
+        Ok(None)
     } else {
-        Some(to_datetime(timestamp))
+        to_datetime(timestamp).map(Some)
     }
 }
 
@@ -183,12 +476,205 @@ pub fn to_optional_datetime(timestamp: u64) -> Option<DateTime<Utc>> {
 mod tests {
     use std::str::FromStr;
 
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use file_store::FileInfo;
     use geo::{Contains, Point};
     use geojson::GeoJson;
     use h3o::{CellIndex, LatLng};
-    use sqlx::{postgres::PgPoolOptions, Row};
+    use sqlx::{postgres::PgPoolOptions, Postgres, Row, Transaction};
     use tokio::fs;
 
+    use bytes::BytesMut;
+    use futures::{StreamExt, TryStreamExt};
+
+    use super::{decode_batches, Batch, CleanTarget, Insertable, Record, RecordErrors};
+    use crate::record_errors::RawRecord;
+
+    #[derive(Debug)]
+    struct TestRecord(DateTime<Utc>);
+
+    impl Record for TestRecord {
+        fn timestamp(&self) -> Option<DateTime<Utc>> {
+            Some(self.0)
+        }
+
+        fn to_json(&self) -> serde_json::Value {
+            serde_json::json!({ "timestamp": self.0 })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Insertable for Vec<TestRecord> {
+        async fn insert(
+            &self,
+            _transaction: &mut Transaction<'_, Postgres>,
+            _file_info: &FileInfo,
+            _errors: &mut RecordErrors,
+        ) -> anyhow::Result<usize> {
+            Ok(self.len())
+        }
+
+        fn partition_keys(&self, _file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+            self.iter()
+                .map(|record| ("test_records", record.0))
+                .collect()
+        }
+    }
+
+    fn file_info() -> FileInfo {
+        FileInfo {
+            key: "test_record.1700000000000.gz".to_string(),
+            prefix: "test_record".to_string(),
+            timestamp: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            size: 0,
+        }
+    }
+
+    fn raw(offset: u64) -> RawRecord {
+        RawRecord {
+            offset,
+            bytes: Default::default(),
+        }
+    }
+
+    fn decode_test_record(buf: BytesMut) -> Result<TestRecord, std::fmt::Error> {
+        if buf.as_ref() == b"bad" {
+            return Err(std::fmt::Error);
+        }
+        Ok(TestRecord(Utc.timestamp_opt(buf.len() as i64, 0).unwrap()))
+    }
+
+    async fn undecodable(keep_raw: bool) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+        let records = ["a", "bad", "cc", "bad"]
+            .into_iter()
+            .map(|record| Ok(BytesMut::from(record.as_bytes())));
+        let batches: Vec<_> = decode_batches(
+            futures::stream::iter(records).boxed(),
+            keep_raw,
+            decode_test_record,
+        )
+        .try_collect()
+        .await?;
+
+        Ok(batches
+            .iter()
+            .flat_map(|batch| batch.undecodable())
+            .map(|dead_letter| (dead_letter.raw.offset, dead_letter.raw.bytes.to_vec()))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn decode_batches_tracks_record_offsets() -> anyhow::Result<()> {
+        // each record is preceded by a 4 byte length: a at 0, bad at 5, cc at 12, bad at 18
+        assert_eq!(
+            undecodable(true).await?,
+            vec![(5, b"bad".to_vec()), (18, b"bad".to_vec())]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decode_batches_drops_raw_bytes_unless_kept() -> anyhow::Result<()> {
+        assert_eq!(undecodable(false).await?, vec![(5, vec![]), (18, vec![])]);
+        Ok(())
+    }
+
+    #[test]
+    fn batch_partition_keys_are_distinct() {
+        let first = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let second = first + Duration::hours(1);
+        let batch = Batch::from(vec![
+            (raw(0), Ok(TestRecord(second))),
+            (raw(10), Ok(TestRecord(first))),
+            (raw(20), Ok(TestRecord(second))),
+            (raw(30), Err("undecodable".to_string())),
+            (raw(40), Ok(TestRecord(first))),
+        ]);
+
+        assert_eq!(
+            batch.partition_keys(&file_info()),
+            vec![("test_records", first), ("test_records", second)]
+        );
+    }
+
+    const WINDOW: &str = "($1::timestamptz IS NULL OR file_timestamp >= $1) AND ($2::timestamptz IS NULL OR file_timestamp < $2)";
+
+    #[test]
+    fn clean_target_from_where() {
+        assert_eq!(
+            CleanTarget::Table {
+                table: "mobile_gateway_rewards",
+            }
+            .from_where(),
+            format!("FROM mobile_gateway_rewards WHERE {}", WINDOW)
+        );
+        assert_eq!(
+            CleanTarget::FilteredTable {
+                table: "radio_thresholds",
+                condition: "validated",
+            }
+            .from_where(),
+            format!("FROM radio_thresholds WHERE validated AND {}", WINDOW)
+        );
+        assert_eq!(
+            CleanTarget::ChildTable {
+                table: "location_trust_scores",
+                parent: "mobile_radio_rewards_v2",
+            }
+            .from_where(),
+            format!(
+                "FROM location_trust_scores WHERE id IN (SELECT id FROM mobile_radio_rewards_v2 WHERE {})",
+                WINDOW
+            )
+        );
+    }
+
+    #[test]
+    fn clean_target_from_unstamped() {
+        assert_eq!(
+            CleanTarget::Table {
+                table: "mobile_gateway_rewards",
+            }
+            .from_unstamped(),
+            "FROM mobile_gateway_rewards WHERE file_timestamp IS NULL"
+        );
+        assert_eq!(
+            CleanTarget::ChildTable {
+                table: "location_trust_scores",
+                parent: "mobile_radio_rewards_v2",
+            }
+            .from_unstamped(),
+            "FROM location_trust_scores WHERE id IN (SELECT id FROM mobile_radio_rewards_v2 WHERE file_timestamp IS NULL)"
+        );
+    }
+
+    #[test]
+    fn clean_target_from_file_key() {
+        assert_eq!(
+            CleanTarget::Table {
+                table: "mobile_gateway_rewards",
+            }
+            .from_file_key(),
+            "FROM mobile_gateway_rewards WHERE file_key = $1"
+        );
+        assert_eq!(
+            CleanTarget::FilteredTable {
+                table: "radio_thresholds",
+                condition: "validated",
+            }
+            .from_file_key(),
+            "FROM radio_thresholds WHERE validated AND file_key = $1"
+        );
+        assert_eq!(
+            CleanTarget::ChildTable {
+                table: "location_trust_scores",
+                parent: "mobile_radio_rewards_v2",
+            }
+            .from_file_key(),
+            "FROM location_trust_scores WHERE file_key = $1"
+        );
+    }
+
     #[tokio::test]
     async fn brian() -> anyhow::Result<()> {
         let pool = PgPoolOptions::new()
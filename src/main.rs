@@ -3,7 +3,8 @@ use clap::Parser;
 
 use h3o::{CellIndex, LatLng};
 use oracle_persist::commands::{
-    animal_names::AnimalNames, clean::Clean, import::Import, reward_analyzer::RewardAnalyzer,
+    animal_names::AnimalNames, clean::Clean, dump::Dump, import::Import, migrate::Migrate,
+    reward_analyzer::RewardAnalyzer, threshold_status::ThresholdStatus,
 };
 use rust_decimal::{prelude::ToPrimitive, Decimal};
 use sqlx::Row;
@@ -18,6 +19,9 @@ struct Args {
 enum Cmd {
     Import(Import),
     Clean(Clean),
+    Dump(Dump),
+    Migrate(Migrate),
+    ThresholdStatus(ThresholdStatus),
     AssertedDistance(AssertedDistance),
     ToHex(ToHex),
     AnimalNames(AnimalNames),
@@ -101,6 +105,9 @@ impl Cmd {
         match self {
             Cmd::Import(import) => import.run().await,
             Cmd::Clean(clean) => clean.run().await,
+            Cmd::Dump(dump) => dump.run().await,
+            Cmd::Migrate(migrate) => migrate.run().await,
+            Cmd::ThresholdStatus(threshold_status) => threshold_status.run().await,
             Cmd::AssertedDistance(asserted_distance) => asserted_distance.run().await,
             Cmd::ToHex(to_hex) => to_hex.run().await,
             Cmd::AnimalNames(an) => an.run().await,
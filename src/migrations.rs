@@ -0,0 +1,114 @@
+use chrono::Utc;
+use sqlx::{Executor, Pool, Postgres};
+
+use crate::SupportedFileTypes;
+
+// A numbered schema change for a file type's tables. `create_table` always builds the
+// latest schema, so migrations also run against fresh tables and must be idempotent,
+// e.g. `ADD COLUMN IF NOT EXISTS`.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub version: i32,
+    pub sql: String,
+}
+
+// Adds the file_key and file_timestamp columns every imported row records to tables
+// created before they existed. Tables that don't exist are skipped.
+pub fn provenance_migration(tables: &[&str]) -> String {
+    tables
+        .iter()
+        .map(|table| {
+            format!(
+                "ALTER TABLE IF EXISTS {} ADD COLUMN IF NOT EXISTS file_key text, ADD COLUMN IF NOT EXISTS file_timestamp timestamptz",
+                table
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(";\n")
+}
+
+pub async fn create_table(db: &Pool<Postgres>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS schema_version (
+                file_type text primary key,
+                version integer not null,
+                migrated_at timestamptz not null
+            )
+        "#,
+    )
+    .execute(db)
+    .await
+    .map(|_| ())
+    .map_err(anyhow::Error::from)
+}
+
+pub async fn current_version(db: &Pool<Postgres>, file_type: &str) -> anyhow::Result<i32> {
+    let version = sqlx::query_scalar(
+        r#"
+            SELECT version FROM schema_version WHERE file_type = $1
+        "#,
+    )
+    .bind(file_type)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(version.unwrap_or(0))
+}
+
+// Creates the file type's tables if they are missing and applies any migrations newer
+// than the recorded schema version, returning the versions that were applied.
+pub async fn run(db: &Pool<Postgres>, file_type: &SupportedFileTypes) -> anyhow::Result<Vec<i32>> {
+    create_table(db).await?;
+    file_type.create_table(db).await?;
+
+    let key = file_type.key();
+    let current = current_version(db, &key).await?;
+    let mut pending: Vec<Migration> = file_type
+        .migrations()
+        .into_iter()
+        .filter(|migration| migration.version > current)
+        .collect();
+    pending.sort_by_key(|migration| migration.version);
+
+    let Some(latest) = pending.last().map(|migration| migration.version) else {
+        return Ok(vec![]);
+    };
+
+    let mut transaction = db.begin().await?;
+    for migration in &pending {
+        // executed without binds so a migration can hold several statements
+        (&mut transaction).execute(migration.sql.as_str()).await?;
+    }
+    sqlx::query(
+        r#"
+            INSERT INTO schema_version(file_type, version, migrated_at)
+            VALUES($1, $2, $3)
+            ON CONFLICT (file_type) DO UPDATE SET
+                version = EXCLUDED.version,
+                migrated_at = EXCLUDED.migrated_at
+        "#,
+    )
+    .bind(&key)
+    .bind(latest)
+    .bind(Utc::now())
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(pending.iter().map(|migration| migration.version).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::provenance_migration;
+
+    #[test]
+    fn provenance_migration_alters_every_table() {
+        assert_eq!(
+            provenance_migration(&["iot_gateway_rewards", "iot_other_rewards"]),
+            "ALTER TABLE IF EXISTS iot_gateway_rewards ADD COLUMN IF NOT EXISTS file_key text, ADD COLUMN IF NOT EXISTS file_timestamp timestamptz;\n\
+             ALTER TABLE IF EXISTS iot_other_rewards ADD COLUMN IF NOT EXISTS file_key text, ADD COLUMN IF NOT EXISTS file_timestamp timestamptz"
+        );
+    }
+}
@@ -1,8 +1,7 @@
 use std::str::FromStr;
 
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{
     services::poc_mobile::{
@@ -12,43 +11,26 @@ use helium_proto::{
 };
 use radio_reward_v2::BulkRadioRewardV2;
 use rust_decimal::Decimal;
-use sqlx::{
-    postgres::{PgHasArrayType, PgTypeInfo},
-    Pool, Postgres,
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    partitions::Partitioned,
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
 };
 
-use crate::{to_datetime, DbTable, Decode, Insertable, ToPrefix};
-
 mod radio_reward_v2;
 
-#[derive(Debug, Clone, sqlx::Type)]
-#[sqlx(type_name = "boosted_hex")]
-struct BoostedHex {
-    location: i64,
-    multiplier: i32,
-}
-
-impl PgHasArrayType for BoostedHex {
-    fn array_type_info() -> PgTypeInfo {
-        PgTypeInfo::with_name("_boosted_hex")
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct FileTypeMobileRewardShare {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeMobileRewardShare {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(
-                |buf| async move { MobileRewardShare::decode(buf).map_err(anyhow::Error::from) },
-            )
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, MobileRewardShare::decode)
     }
 }
 
@@ -58,6 +40,22 @@ impl ToPrefix for FileTypeMobileRewardShare {
     }
 }
 
+const COVERED_HEXES_COLUMNS: &str = r#"
+    id bigint NOT NULL,
+    location int8 NOT NULL,
+    base_coverage_points numeric NOT NULL,
+    boosted_coverage_points numeric NOT NULL,
+    urbanized text NOT NULL,
+    footfall text NOT NULL,
+    landtype text NOT NULL,
+    assignment_multiplier numeric NOT NULL,
+    rank int4 NOT NULL,
+    rank_multiplier numeric NOT NULL,
+    boosted_multiplier int4 NOT NULL,
+    file_key text,
+    file_timestamp timestamptz
+"#;
+
 #[async_trait::async_trait]
 impl DbTable for FileTypeMobileRewardShare {
     async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
@@ -87,7 +85,9 @@ impl DbTable for FileTypeMobileRewardShare {
                 	end_period timestamptz NULL,
                 	location_trust_score_multiplier int4 NOT NULL,
                 	speedtest_multiplier int4 NOT NULL,
-                	transfer_amount int8 NULL
+                	transfer_amount int8 NULL,
+                	file_key text,
+                	file_timestamp timestamptz
                 )
             "#,
         )
@@ -113,7 +113,9 @@ impl DbTable for FileTypeMobileRewardShare {
                 	location_trust_score_multiplier numeric NOT NULL,
                 	speedtest_multiplier numeric NOT NULL,
                 	sp_boosted_hex_status text NOT NULL,
-                	oracle_boosted_hex_status text NOT NULL
+                	oracle_boosted_hex_status text NOT NULL,
+                	file_key text,
+                	file_timestamp timestamptz
                 )
             "#,
         )
@@ -125,7 +127,9 @@ impl DbTable for FileTypeMobileRewardShare {
                 CREATE TABLE IF NOT EXISTS location_trust_scores (
                     id bigint NOT NULL,
                     meters_to_asserted int8 NOT NULL,
-                    trust_score numeric NOT NULL
+                    trust_score numeric NOT NULL,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -134,12 +138,14 @@ impl DbTable for FileTypeMobileRewardShare {
 
         sqlx::query(
             r#"
-                CREATE TABLE IF NOT EXISTS speedtests (
+                CREATE TABLE IF NOT EXISTS radio_reward_speedtests (
                     id bigint NOT NULL,
                     upload int8 NOT NULL,
                     download int8 NOT NULL,
                     latency int4 NOT NULL,
-                    timestamp timestamptz NOT NULL
+                    timestamp timestamptz NOT NULL,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -148,35 +154,23 @@ impl DbTable for FileTypeMobileRewardShare {
 
         sqlx::query(
             r#"
-                CREATE TABLE IF NOT EXISTS speedtest_average (
+                CREATE TABLE IF NOT EXISTS radio_reward_speedtest_average (
                     id bigint NOT NULL,
                     upload int8 NOT NULL,
                     download int8 NOT NULL,
                     latency int4 NOT NULL,
-                    timestamp timestamptz NOT NULL
+                    timestamp timestamptz NOT NULL,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
         .execute(db)
         .await?;
 
-        sqlx::query(
-            r#"
-                CREATE TABLE IF NOT EXISTS covered_hexes (
-                    id bigint NOT NULL,
-                    location int8 NOT NULL,
-                    base_coverage_points numeric NOT NULL,
-                    boosted_coverage_points numeric NOT NULL,
-                    urbanized text NOT NULL,
-                    footfall text NOT NULL,
-                    landtype text NOT NULL,
-                    assignment_multiplier numeric NOT NULL,
-                    rank int4 NOT NULL,
-                    rank_multiplier numeric NOT NULL,
-                    boosted_multiplier int4 NOT NULL
-                )
-            "#,
-        )
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS covered_hexes ({COVERED_HEXES_COLUMNS})"
+        ))
         .execute(db)
         .await?;
 
@@ -189,7 +183,8 @@ impl DbTable for FileTypeMobileRewardShare {
                 	start_period timestamptz NOT NULL,
                 	end_period timestamptz NOT NULL,
                 	price int8 NOT NULL,
-                	file_timestamp timestamptz
+                	file_timestamp timestamptz,
+                	file_key text
                 )
             "#,
         )
@@ -202,7 +197,9 @@ impl DbTable for FileTypeMobileRewardShare {
                     service_provider text not null,
                     amount bigint not null,
                     start_period timestamptz not null,
-                    end_period timestamptz not null
+                    end_period timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -216,7 +213,9 @@ impl DbTable for FileTypeMobileRewardShare {
                     end_period timestamptz not null,
                     entity text not null,
                     service_provider_amount int8 not null,
-                    matched_amount int8 not null
+                    matched_amount int8 not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -229,7 +228,9 @@ impl DbTable for FileTypeMobileRewardShare {
                     reward_type text not null,
                     amount bigint not null,
                     start_period timestamptz not null,
-                    end_period timestamptz not null
+                    end_period timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
         "#,
         )
@@ -243,7 +244,9 @@ impl DbTable for FileTypeMobileRewardShare {
                 	disco_amount int8 NOT NULL,
                 	verification_amount int8 NOT NULL,
                 	start_period timestamptz NOT NULL,
-                	end_period timestamptz NOT NULL
+                	end_period timestamptz NOT NULL,
+                	file_key text,
+                	file_timestamp timestamptz
                 )
             "#,
         )
@@ -252,6 +255,148 @@ impl DbTable for FileTypeMobileRewardShare {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![
+            CleanTarget::ChildTable {
+                table: "location_trust_scores",
+                parent: "mobile_radio_rewards_v2",
+            },
+            CleanTarget::ChildTable {
+                table: "radio_reward_speedtests",
+                parent: "mobile_radio_rewards_v2",
+            },
+            CleanTarget::ChildTable {
+                table: "radio_reward_speedtest_average",
+                parent: "mobile_radio_rewards_v2",
+            },
+            CleanTarget::ChildTable {
+                table: "covered_hexes",
+                parent: "mobile_radio_rewards_v2",
+            },
+            CleanTarget::Table {
+                table: "mobile_radio_rewards_v2",
+            },
+            CleanTarget::Table {
+                table: "mobile_radio_rewards",
+            },
+            CleanTarget::Table {
+                table: "mobile_gateway_rewards",
+            },
+            CleanTarget::Table {
+                table: "mobile_service_provider_rewards",
+            },
+            CleanTarget::Table {
+                table: "mobile_promotion_rewards",
+            },
+            CleanTarget::Table {
+                table: "mobile_unallocated_rewards",
+            },
+            CleanTarget::Table {
+                table: "mobile_subscriber_rewards",
+            },
+        ]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![
+            Migration {
+                version: 1,
+                sql: provenance_migration(&[
+                    "mobile_radio_rewards",
+                    "mobile_radio_rewards_v2",
+                    "location_trust_scores",
+                    "speedtests",
+                    "speedtest_average",
+                    "covered_hexes",
+                    "mobile_gateway_rewards",
+                    "mobile_service_provider_rewards",
+                    "mobile_promotion_rewards",
+                    "mobile_unallocated_rewards",
+                    "mobile_subscriber_rewards",
+                ]),
+            },
+            // reward share speedtests used to live in speedtests and speedtest_average, speedtests
+            // now belongs to the verified speedtests. create_table has already made empty
+            // radio_reward_ tables, those are replaced by the renamed ones. Fresh databases never
+            // had the old tables, version 1 skips them there.
+            Migration {
+                version: 2,
+                sql: format!(
+                    r#"
+                    DO $$ BEGIN
+                        IF EXISTS (
+                            SELECT 1 FROM information_schema.columns
+                            WHERE table_schema = current_schema() AND table_name = 'speedtests' AND column_name = 'id'
+                        ) THEN
+                            DROP TABLE radio_reward_speedtests;
+                            ALTER TABLE speedtests RENAME TO radio_reward_speedtests;
+                            ALTER INDEX IF EXISTS speedtests_id_idx RENAME TO radio_reward_speedtests_id_idx;
+                        END IF;
+                        IF to_regclass('speedtest_average') IS NOT NULL THEN
+                            DROP TABLE radio_reward_speedtest_average;
+                            ALTER TABLE speedtest_average RENAME TO radio_reward_speedtest_average;
+                            ALTER INDEX IF EXISTS speedtest_average_id_idx RENAME TO radio_reward_speedtest_average_id_idx;
+                        END IF;
+                    END $$;
+                    {}
+                "#,
+                    provenance_migration(&[
+                        "radio_reward_speedtests",
+                        "radio_reward_speedtest_average",
+                    ])
+                ),
+            },
+        ]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![
+            Index {
+                name: "mobile_radio_rewards_hotspot_key_idx",
+                table: "mobile_radio_rewards",
+                columns: "hotspot_key, end_period",
+            },
+            Index {
+                name: "mobile_radio_rewards_v2_hotspot_key_idx",
+                table: "mobile_radio_rewards_v2",
+                columns: "hotspot_key, end_period",
+            },
+            Index {
+                name: "location_trust_scores_id_idx",
+                table: "location_trust_scores",
+                columns: "id",
+            },
+            Index {
+                name: "radio_reward_speedtests_id_idx",
+                table: "radio_reward_speedtests",
+                columns: "id",
+            },
+            Index {
+                name: "radio_reward_speedtest_average_id_idx",
+                table: "radio_reward_speedtest_average",
+                columns: "id",
+            },
+            Index {
+                name: "covered_hexes_id_idx",
+                table: "covered_hexes",
+                columns: "id",
+            },
+            Index {
+                name: "mobile_gateway_rewards_hotspot_key_idx",
+                table: "mobile_gateway_rewards",
+                columns: "hotspot_key, end_period",
+            },
+        ]
+    }
+
+    fn partitioned(&self) -> Vec<Partitioned> {
+        vec![Partitioned {
+            table: "covered_hexes",
+            column: "file_timestamp",
+            columns: COVERED_HEXES_COLUMNS,
+        }]
+    }
 }
 
 #[async_trait::async_trait]
@@ -259,101 +404,128 @@ impl Insertable for Vec<MobileRewardShare> {
     #[allow(deprecated)]
     async fn insert(
         &self,
-        pool: &Pool<Postgres>,
-        file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
         let mut bulk_radio_reward = BulkRadioReward::default();
         let mut bulk_gateway_reward = BulkGatewayReward::default();
         let mut bulk_subscriber_reward = BulkSubscriberReward::default();
         let mut bulk_radio_reward_v2 = BulkRadioRewardV2::default();
 
-        for share in self {
+        let mut written = 0;
+        for (index, share) in self.iter().enumerate() {
+            let periods = to_datetime(share.start_period)
+                .and_then(|start_period| Ok((start_period, to_datetime(share.end_period)?)));
+            let (start_period, end_period) = match periods {
+                Ok(periods) => periods,
+                Err(err) => {
+                    errors.push(index, err)?;
+                    continue;
+                }
+            };
+
             match share.reward.clone() {
                 Some(mobile_reward_share::Reward::RadioReward(radio)) => {
-                    bulk_radio_reward.add(
-                        to_datetime(share.start_period),
-                        to_datetime(share.end_period),
-                        radio,
-                    );
+                    bulk_radio_reward.add(start_period, end_period, radio);
                 }
                 Some(mobile_reward_share::Reward::RadioRewardV2(reward)) => {
-                    bulk_radio_reward_v2.add(
-                        reward,
-                        to_datetime(share.start_period),
-                        to_datetime(share.end_period)
-                    );
+                    if let Err(err) = bulk_radio_reward_v2.add(reward, start_period, end_period) {
+                        errors.push(index, err)?;
+                        continue;
+                    }
                 }
                 Some(mobile_reward_share::Reward::GatewayReward(gateway)) => {
-                    bulk_gateway_reward.add(
-                        to_datetime(share.start_period),
-                        to_datetime(share.end_period),
-                        file_timestamp,
-                        gateway,
-                    );
+                    bulk_gateway_reward.add(start_period, end_period, gateway);
                 }
                 Some(mobile_reward_share::Reward::SubscriberReward(sub)) => {
-                    bulk_subscriber_reward.add(
-                        to_datetime(share.start_period),
-                        to_datetime(share.end_period),
-                        sub,
-                    );
+                    bulk_subscriber_reward.add(start_period, end_period, sub);
                 }
-                Some(mobile_reward_share::Reward::ServiceProviderReward(service)) => sqlx::query(
-                    r#"
-                        INSERT INTO mobile_service_provider_rewards(service_provider, amount, start_period, end_period) VALUES($1,$2,$3,$4)
-                    "#
+                Some(mobile_reward_share::Reward::ServiceProviderReward(service)) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO mobile_service_provider_rewards(service_provider, amount, start_period, end_period, file_key, file_timestamp)
+                        VALUES($1, $2, $3, $4, $5, $6)
+                        "#,
                     )
                     .bind(service.service_provider_id().as_str_name())
                     .bind(service.amount as i64)
-                    .bind(to_datetime(share.start_period))
-                    .bind(to_datetime(share.end_period))
-                    .execute(pool)
-                    .await
-                    .map(|_| ())?,
-                Some(mobile_reward_share::Reward::PromotionReward(promotion)) => sqlx::
-                    query(
-                      r#"
-                        INSERT INTO mobile_promotion_rewards(start_period, end_period, entity, service_provider_amount, matched_amount)
-                        VALUES($1, $2, $3, $4, $5)
-                      "#
-                      )
-                    .bind(to_datetime(share.start_period))
-                    .bind(to_datetime(share.end_period))
+                    .bind(start_period)
+                    .bind(end_period)
+                    .bind(&file_info.key)
+                    .bind(file_info.timestamp)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                Some(mobile_reward_share::Reward::PromotionReward(promotion)) => {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO mobile_promotion_rewards(start_period, end_period, entity, service_provider_amount, matched_amount, file_key, file_timestamp)
+                        VALUES($1, $2, $3, $4, $5, $6, $7)
+                        "#,
+                    )
+                    .bind(start_period)
+                    .bind(end_period)
                     .bind(&promotion.entity)
                     .bind(promotion.service_provider_amount as i64)
                     .bind(promotion.matched_amount as i64)
-                    .execute(pool)
-                    .await
-                    .map(|_| ())?,
+                    .bind(&file_info.key)
+                    .bind(file_info.timestamp)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
                 Some(mobile_reward_share::Reward::UnallocatedReward(unallocated)) => {
                     sqlx::query(
-                    r#"
-                        INSERT INTO mobile_unallocated_rewards(reward_type, amount, start_period, end_period) VALUES($1,$2,$3,$4)
-                    "#
+                        r#"
+                        INSERT INTO mobile_unallocated_rewards(reward_type, amount, start_period, end_period, file_key, file_timestamp)
+                        VALUES($1, $2, $3, $4, $5, $6)
+                        "#,
                     )
                     .bind(unallocated.reward_type().as_str_name())
                     .bind(unallocated.amount as i64)
-                    .bind(to_datetime(share.start_period))
-                    .bind(to_datetime(share.end_period))
-                    .execute(pool)
-                    .await
-                    .map(|_| ())?},
-                _ => (),
-            };
+                    .bind(start_period)
+                    .bind(end_period)
+                    .bind(&file_info.key)
+                    .bind(file_info.timestamp)
+                    .execute(&mut *transaction)
+                    .await?;
+                }
+                _ => continue,
+            }
+            written += 1;
         }
 
-        bulk_radio_reward.insert(pool).await?;
-        bulk_radio_reward_v2.insert(pool).await?;
-        bulk_gateway_reward.insert(pool).await?;
-        bulk_subscriber_reward.insert(pool).await?;
-        Ok(())
+        bulk_radio_reward.insert(transaction, file_info).await?;
+        bulk_radio_reward_v2.insert(transaction, file_info).await?;
+        bulk_gateway_reward.insert(transaction, file_info).await?;
+        bulk_subscriber_reward
+            .insert(transaction, file_info)
+            .await?;
+        Ok(written)
+    }
+
+    fn partition_keys(&self, file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+        // covered hexes are partitioned on the file timestamp, the only time they carry
+        let has_covered_hexes = self.iter().any(|share| {
+            matches!(
+                share.reward,
+                Some(mobile_reward_share::Reward::RadioRewardV2(_))
+            )
+        });
+        if has_covered_hexes {
+            vec![("covered_hexes", file_info.timestamp)]
+        } else {
+            vec![]
+        }
     }
 }
 
-fn from_proto_decimal(opt: Option<&helium_proto::Decimal>) -> Decimal {
-    opt.ok_or_else(|| anyhow::anyhow!("decimal not present"))
-        .and_then(|d| Decimal::from_str(&d.value).map_err(anyhow::Error::from))
-        .unwrap_or_default()
+fn from_proto_decimal(
+    field: &'static str,
+    opt: Option<&helium_proto::Decimal>,
+) -> Result<Decimal, RecordError> {
+    let decimal = opt.ok_or(RecordError::MissingField(field))?;
+    Decimal::from_str(&decimal.value).map_err(RecordError::invalid(field))
 }
 
 #[derive(Default)]
@@ -374,11 +546,8 @@ impl BulkRadioReward {
     fn add(&mut self, start_period: DateTime<Utc>, end_period: DateTime<Utc>, radio: RadioReward) {
         self.start_period.push(start_period);
         self.end_period.push(end_period);
-        self.hotspot_key.push(
-            PublicKeyBinary::try_from(radio.hotspot_key)
-                .unwrap()
-                .to_string(),
-        );
+        self.hotspot_key
+            .push(PublicKeyBinary::from(radio.hotspot_key).to_string());
         self.cbsd_id.push(radio.cbsd_id);
         self.coverage_points.push(radio.coverage_points as i64);
         self.poc_reward.push(radio.poc_reward as i64);
@@ -390,11 +559,15 @@ impl BulkRadioReward {
             .push(radio.speedtest_multiplier as i32);
     }
 
-    async fn insert(self, db: &Pool<Postgres>) -> anyhow::Result<()> {
+    async fn insert(
+        self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+    ) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO mobile_radio_rewards(hotspot_key, cbsd_id, coverage_points, amount, start_period, end_period, transfer_amount, location_trust_score_multiplier, speedtest_multiplier)
-            SELECT * FROM UNNEST($1,$2,$3,$4,$5,$6,$7,$8,$9)
+            INSERT INTO mobile_radio_rewards(hotspot_key, cbsd_id, coverage_points, amount, start_period, end_period, transfer_amount, location_trust_score_multiplier, speedtest_multiplier, file_key, file_timestamp)
+            SELECT *, $10, $11 FROM UNNEST($1,$2,$3,$4,$5,$6,$7,$8,$9)
             "#,
         )
         .bind(self.hotspot_key)
@@ -406,7 +579,9 @@ impl BulkRadioReward {
         .bind(self.dc_transfer_reward)
         .bind(self.location_trust_score_multiplier)
         .bind(self.speedtest_multiplier)
-        .execute(db)
+        .bind(&file_info.key)
+        .bind(file_info.timestamp)
+        .execute(&mut *transaction)
         .await?;
 
         Ok(())
@@ -421,7 +596,6 @@ struct BulkGatewayReward {
     start_period: Vec<DateTime<Utc>>,
     end_period: Vec<DateTime<Utc>>,
     price: Vec<i64>,
-    file_timestamp: Vec<DateTime<Utc>>,
 }
 
 impl BulkGatewayReward {
@@ -429,27 +603,26 @@ impl BulkGatewayReward {
         &mut self,
         start_period: DateTime<Utc>,
         end_period: DateTime<Utc>,
-        file_timestamp: DateTime<Utc>,
         gateway: GatewayReward,
     ) {
         self.start_period.push(start_period);
         self.end_period.push(end_period);
-        self.file_timestamp.push(file_timestamp);
-        self.hotspot_key.push(
-            PublicKeyBinary::try_from(gateway.hotspot_key)
-                .unwrap()
-                .to_string(),
-        );
+        self.hotspot_key
+            .push(PublicKeyBinary::from(gateway.hotspot_key).to_string());
         self.amount.push(gateway.dc_transfer_reward as i64);
         self.rewardable_bytes.push(gateway.rewardable_bytes as i64);
         self.price.push(gateway.price as i64);
     }
 
-    async fn insert(self, db: &Pool<Postgres>) -> anyhow::Result<()> {
+    async fn insert(
+        self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+    ) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-                INSERT INTO mobile_gateway_rewards(hotspot_key, amount, rewardable_bytes, start_period, end_period, price, file_timestamp)
-                SELECT * FROM UNNEST($1, $2, $3, $4, $5, $6, $7)
+                INSERT INTO mobile_gateway_rewards(hotspot_key, amount, rewardable_bytes, start_period, end_period, price, file_key, file_timestamp)
+                SELECT *, $7, $8 FROM UNNEST($1, $2, $3, $4, $5, $6)
             "#,
         )
         .bind(self.hotspot_key)
@@ -458,8 +631,9 @@ impl BulkGatewayReward {
         .bind(self.start_period)
         .bind(self.end_period)
         .bind(self.price)
-        .bind(self.file_timestamp)
-        .execute(db)
+        .bind(&file_info.key)
+        .bind(file_info.timestamp)
+        .execute(&mut *transaction)
         .await?;
 
         Ok(())
@@ -491,11 +665,15 @@ impl BulkSubscriberReward {
         self.end_period.push(end_period);
     }
 
-    async fn insert(self, db: &Pool<Postgres>) -> anyhow::Result<()> {
+    async fn insert(
+        self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+    ) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-                INSERT INTO mobile_subscriber_rewards(subscriber_id, disco_amount, verification_amount, start_period, end_period)
-                SELECT * FROM UNNEST($1, $2, $3, $4, $5)
+                INSERT INTO mobile_subscriber_rewards(subscriber_id, disco_amount, verification_amount, start_period, end_period, file_key, file_timestamp)
+                SELECT *, $6, $7 FROM UNNEST($1, $2, $3, $4, $5)
             "#
         )
         .bind(self.subscriber_id)
@@ -503,9 +681,117 @@ impl BulkSubscriberReward {
         .bind(self.verification_amount)
         .bind(self.start_period)
         .bind(self.end_period)
-        .execute(db)
+        .bind(&file_info.key)
+        .bind(file_info.timestamp)
+        .execute(&mut *transaction)
         .await?;
 
         Ok(())
     }
 }
+
+impl Record for MobileRewardShare {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime(self.end_period).ok()
+    }
+
+    #[allow(deprecated)]
+    fn to_json(&self) -> serde_json::Value {
+        let decimal = |d: Option<&helium_proto::Decimal>| d.map(|d| d.value.clone());
+        let pubkey = |bytes: &Vec<u8>| PublicKeyBinary::from(bytes.clone()).to_string();
+
+        let reward = match &self.reward {
+            Some(mobile_reward_share::Reward::RadioReward(radio)) => json!({
+                "type": "radio",
+                "hotspot_key": pubkey(&radio.hotspot_key),
+                "cbsd_id": radio.cbsd_id,
+                "coverage_points": radio.coverage_points,
+                "poc_reward": radio.poc_reward,
+                "dc_transfer_reward": radio.dc_transfer_reward,
+                "location_trust_score_multiplier": radio.location_trust_score_multiplier,
+                "speedtest_multiplier": radio.speedtest_multiplier,
+            }),
+            Some(mobile_reward_share::Reward::RadioRewardV2(radio)) => json!({
+                "type": "radio_v2",
+                "hotspot_key": pubkey(&radio.hotspot_key),
+                "cbsd_id": radio.cbsd_id,
+                "base_coverage_points_sum": decimal(radio.base_coverage_points_sum.as_ref()),
+                "boosted_coverage_points_sum": decimal(radio.boosted_coverage_points_sum.as_ref()),
+                "base_reward_shares": decimal(radio.base_reward_shares.as_ref()),
+                "boosted_reward_shares": decimal(radio.boosted_reward_shares.as_ref()),
+                "base_poc_reward": radio.base_poc_reward,
+                "boosted_poc_reward": radio.boosted_poc_reward,
+                "seniority_ts": to_datetime(radio.seniority_timestamp).ok(),
+                "coverage_object": uuid::Uuid::from_slice(&radio.coverage_object).ok(),
+                "location_trust_score_multiplier": decimal(radio.location_trust_score_multiplier.as_ref()),
+                "speedtest_multiplier": decimal(radio.speedtest_multiplier.as_ref()),
+                "sp_boosted_hex_status": radio.sp_boosted_hex_status().as_str_name(),
+                "oracle_boosted_hex_status": radio.oracle_boosted_hex_status().as_str_name(),
+                "location_trust_scores": radio.location_trust_scores.iter().map(|lt| json!({
+                    "meters_to_asserted": lt.meters_to_asserted,
+                    "trust_score": decimal(lt.trust_score.as_ref()),
+                })).collect::<Vec<_>>(),
+                "speedtests": radio.speedtests.iter().map(|st| json!({
+                    "upload_speed_bps": st.upload_speed_bps,
+                    "download_speed_bps": st.download_speed_bps,
+                    "latency_ms": st.latency_ms,
+                    "timestamp": to_datetime(st.timestamp).ok(),
+                })).collect::<Vec<_>>(),
+                "speedtest_average": radio.speedtest_average.as_ref().map(|sa| json!({
+                    "upload_speed_bps": sa.upload_speed_bps,
+                    "download_speed_bps": sa.download_speed_bps,
+                    "latency_ms": sa.latency_ms,
+                    "timestamp": to_datetime(sa.timestamp).ok(),
+                })),
+                "covered_hexes": radio.covered_hexes.iter().map(|h| json!({
+                    "location": h.location,
+                    "base_coverage_points": decimal(h.base_coverage_points.as_ref()),
+                    "boosted_coverage_points": decimal(h.boosted_coverage_points.as_ref()),
+                    "urbanized": h.urbanized().as_str_name(),
+                    "footfall": h.footfall().as_str_name(),
+                    "landtype": h.landtype().as_str_name(),
+                    "assignment_multiplier": decimal(h.assignment_multiplier.as_ref()),
+                    "rank": h.rank,
+                    "rank_multiplier": decimal(h.rank_multiplier.as_ref()),
+                    "boosted_multiplier": h.boosted_multiplier,
+                })).collect::<Vec<_>>(),
+            }),
+            Some(mobile_reward_share::Reward::GatewayReward(gateway)) => json!({
+                "type": "gateway",
+                "hotspot_key": pubkey(&gateway.hotspot_key),
+                "dc_transfer_reward": gateway.dc_transfer_reward,
+                "rewardable_bytes": gateway.rewardable_bytes,
+                "price": gateway.price,
+            }),
+            Some(mobile_reward_share::Reward::SubscriberReward(sub)) => json!({
+                "type": "subscriber",
+                "subscriber_id": bs58::encode(&sub.subscriber_id).into_string(),
+                "discovery_location_amount": sub.discovery_location_amount,
+                "verification_mapping_amount": sub.verification_mapping_amount,
+            }),
+            Some(mobile_reward_share::Reward::ServiceProviderReward(service)) => json!({
+                "type": "service_provider",
+                "service_provider": service.service_provider_id().as_str_name(),
+                "amount": service.amount,
+            }),
+            Some(mobile_reward_share::Reward::PromotionReward(promotion)) => json!({
+                "type": "promotion",
+                "entity": promotion.entity,
+                "service_provider_amount": promotion.service_provider_amount,
+                "matched_amount": promotion.matched_amount,
+            }),
+            Some(mobile_reward_share::Reward::UnallocatedReward(unallocated)) => json!({
+                "type": "unallocated",
+                "reward_type": unallocated.reward_type().as_str_name(),
+                "amount": unallocated.amount,
+            }),
+            _ => serde_json::Value::Null,
+        };
+
+        json!({
+            "start_period": to_datetime(self.start_period).ok(),
+            "end_period": to_datetime(self.end_period).ok(),
+            "reward": reward,
+        })
+    }
+}
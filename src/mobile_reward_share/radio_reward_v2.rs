@@ -1,23 +1,47 @@
-
 use chrono::{DateTime, Utc};
-use futures::{stream, StreamExt, TryStreamExt};
+use file_store::FileInfo;
 use helium_crypto::PublicKeyBinary;
 use helium_proto::services::poc_mobile::RadioRewardV2;
-use sqlx::{Pool, Postgres, QueryBuilder, Row, Transaction};
+use rust_decimal::Decimal;
+use sqlx::{Postgres, QueryBuilder, Row, Transaction};
 use uuid::Uuid;
 
-use crate::to_datetime;
+use super::from_proto_decimal;
+use crate::{pg_copy, record_errors::RecordError, to_datetime};
 
+// A reward with every timestamp and decimal already converted, so a bad one is reported
+// against its record in `add` rather than failing the bulk insert.
 #[derive(Debug, Clone)]
 struct Reward {
     reward: RadioRewardV2,
+    coverage_object: Uuid,
     start_period: DateTime<Utc>,
     end_period: DateTime<Utc>,
+    seniority_ts: DateTime<Utc>,
+    base_coverage_points_sum: Decimal,
+    boosted_coverage_points_sum: Decimal,
+    base_reward_shares: Decimal,
+    boosted_reward_shares: Decimal,
+    location_trust_score_multiplier: Decimal,
+    speedtest_multiplier: Decimal,
+    // in the same order as the reward's location_trust_scores, speedtests and covered_hexes
+    trust_scores: Vec<Decimal>,
+    speedtest_timestamps: Vec<DateTime<Utc>>,
+    speedtest_average_timestamp: Option<DateTime<Utc>>,
+    covered_hexes: Vec<CoveredHex>,
+}
+
+#[derive(Debug, Clone)]
+struct CoveredHex {
+    base_coverage_points: Decimal,
+    boosted_coverage_points: Decimal,
+    assignment_multiplier: Decimal,
+    rank_multiplier: Decimal,
 }
 
 #[derive(Debug)]
 struct IdentifiedReward {
-    reward: RadioRewardV2,
+    reward: Reward,
     id: i64,
 }
 
@@ -32,38 +56,105 @@ impl BulkRadioRewardV2 {
         reward: RadioRewardV2,
         start_period: DateTime<Utc>,
         end_period: DateTime<Utc>,
-    ) {
+    ) -> Result<(), RecordError> {
+        let coverage_object = Uuid::from_slice(reward.coverage_object.as_slice())
+            .map_err(RecordError::invalid("coverage_object"))?;
+        let trust_scores = reward
+            .location_trust_scores
+            .iter()
+            .map(|lt| from_proto_decimal("trust_score", lt.trust_score.as_ref()))
+            .collect::<Result<_, _>>()?;
+        let speedtest_timestamps = reward
+            .speedtests
+            .iter()
+            .map(|st| to_datetime(st.timestamp))
+            .collect::<Result<_, _>>()?;
+        let speedtest_average_timestamp = reward
+            .speedtest_average
+            .as_ref()
+            .map(|sa| to_datetime(sa.timestamp))
+            .transpose()?;
+        let covered_hexes = reward
+            .covered_hexes
+            .iter()
+            .map(|h| {
+                Ok(CoveredHex {
+                    base_coverage_points: from_proto_decimal(
+                        "base_coverage_points",
+                        h.base_coverage_points.as_ref(),
+                    )?,
+                    boosted_coverage_points: from_proto_decimal(
+                        "boosted_coverage_points",
+                        h.boosted_coverage_points.as_ref(),
+                    )?,
+                    assignment_multiplier: from_proto_decimal(
+                        "assignment_multiplier",
+                        h.assignment_multiplier.as_ref(),
+                    )?,
+                    rank_multiplier: from_proto_decimal(
+                        "rank_multiplier",
+                        h.rank_multiplier.as_ref(),
+                    )?,
+                })
+            })
+            .collect::<Result<_, RecordError>>()?;
+
         self.rewards.push(Reward {
-            reward,
+            coverage_object,
             start_period,
             end_period,
+            seniority_ts: to_datetime(reward.seniority_timestamp)?,
+            base_coverage_points_sum: from_proto_decimal(
+                "base_coverage_points_sum",
+                reward.base_coverage_points_sum.as_ref(),
+            )?,
+            boosted_coverage_points_sum: from_proto_decimal(
+                "boosted_coverage_points_sum",
+                reward.boosted_coverage_points_sum.as_ref(),
+            )?,
+            base_reward_shares: from_proto_decimal(
+                "base_reward_shares",
+                reward.base_reward_shares.as_ref(),
+            )?,
+            boosted_reward_shares: from_proto_decimal(
+                "boosted_reward_shares",
+                reward.boosted_reward_shares.as_ref(),
+            )?,
+            location_trust_score_multiplier: from_proto_decimal(
+                "location_trust_score_multiplier",
+                reward.location_trust_score_multiplier.as_ref(),
+            )?,
+            speedtest_multiplier: from_proto_decimal(
+                "speedtest_multiplier",
+                reward.speedtest_multiplier.as_ref(),
+            )?,
+            trust_scores,
+            speedtest_timestamps,
+            speedtest_average_timestamp,
+            covered_hexes,
+            reward,
         });
+        Ok(())
     }
 
-    pub async fn insert(self, db: &Pool<Postgres>) -> anyhow::Result<()> {
-        let transaction = db.begin().await?;
-
-        const NUM_BATCH: usize = (u16::MAX / 16) as usize;
-
-        let (mut transaction, identified_rewards) = stream::iter(self.rewards)
-            .map(anyhow::Ok)
-            .try_chunks(NUM_BATCH)
-            .err_into::<anyhow::Error>()
-            .try_fold(
-                (transaction, Vec::new()),
-                |(mut tx, mut ids), batch| async move {
-                    ids.append(&mut insert_reward(&mut tx, batch).await?);
-                    Ok((tx, ids))
-                },
-            )
-            .await?;
-
-        insert_location_trust_scores(&mut transaction, &identified_rewards).await?;
-        insert_speedtests(&mut transaction, &identified_rewards).await?;
-        insert_speedtest_averages(&mut transaction, &identified_rewards).await?;
-        insert_covered_hexes(&mut transaction, &identified_rewards).await?;
-
-        transaction.commit().await?;
+    pub async fn insert(
+        self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+    ) -> anyhow::Result<()> {
+        const NUM_BATCH: usize = (u16::MAX / 18) as usize;
+
+        let mut identified_rewards = Vec::with_capacity(self.rewards.len());
+        for batch in self.rewards.chunks(NUM_BATCH) {
+            identified_rewards
+                .append(&mut insert_reward(transaction, batch.to_vec(), file_info).await?);
+        }
+
+        insert_location_trust_scores(transaction, &identified_rewards, file_info).await?;
+        insert_speedtests(transaction, &identified_rewards, file_info).await?;
+        insert_speedtest_averages(transaction, &identified_rewards, file_info).await?;
+        insert_covered_hexes(transaction, &identified_rewards, file_info).await?;
+
         Ok(())
     }
 }
@@ -71,39 +162,40 @@ impl BulkRadioRewardV2 {
 async fn insert_reward(
     tx: &mut Transaction<'_, Postgres>,
     rewards: Vec<Reward>,
+    file_info: &FileInfo,
 ) -> anyhow::Result<Vec<IdentifiedReward>> {
-    let ids = 
-    QueryBuilder::new("INSERT INTO mobile_radio_rewards_v2(start_period, end_period, hotspot_key, cbsd_id, base_coverage_points_sum, boosted_coverage_points_sum, base_reward_shares, boosted_reward_shares, base_poc_reward, boosted_poc_reward, seniority_ts, coverage_object, location_trust_score_multiplier, speedtest_multiplier, sp_boosted_hex_status, oracle_boosted_hex_status)")
+    let ids = QueryBuilder::new("INSERT INTO mobile_radio_rewards_v2(start_period, end_period, hotspot_key, cbsd_id, base_coverage_points_sum, boosted_coverage_points_sum, base_reward_shares, boosted_reward_shares, base_poc_reward, boosted_poc_reward, seniority_ts, coverage_object, location_trust_score_multiplier, speedtest_multiplier, sp_boosted_hex_status, oracle_boosted_hex_status, file_key, file_timestamp)")
     .push_values(rewards.clone(), |mut b, reward| {
         b.push_bind(reward.start_period)
         .push_bind(reward.end_period)
         .push_bind(PublicKeyBinary::from(reward.reward.hotspot_key.as_slice()).to_string())
         .push_bind(reward.reward.cbsd_id.clone())
-        .push_bind(super::from_proto_decimal(reward.reward.base_coverage_points_sum.as_ref()))
-        .push_bind(super::from_proto_decimal(reward.reward.boosted_coverage_points_sum.as_ref()))
-        .push_bind(super::from_proto_decimal(reward.reward.base_reward_shares.as_ref()))
-        .push_bind(super::from_proto_decimal(reward.reward.boosted_reward_shares.as_ref()))
+        .push_bind(reward.base_coverage_points_sum)
+        .push_bind(reward.boosted_coverage_points_sum)
+        .push_bind(reward.base_reward_shares)
+        .push_bind(reward.boosted_reward_shares)
         .push_bind(reward.reward.base_poc_reward as i64)
         .push_bind(reward.reward.boosted_poc_reward as i64)
-        .push_bind(to_datetime(reward.reward.seniority_timestamp))
-        .push_bind(Uuid::from_slice(reward.reward.coverage_object.as_slice()).unwrap())
-        .push_bind(super::from_proto_decimal(reward.reward.location_trust_score_multiplier.as_ref()))
-        .push_bind(super::from_proto_decimal(reward.reward.speedtest_multiplier.as_ref()))
+        .push_bind(reward.seniority_ts)
+        .push_bind(reward.coverage_object)
+        .push_bind(reward.location_trust_score_multiplier)
+        .push_bind(reward.speedtest_multiplier)
         .push_bind(reward.reward.sp_boosted_hex_status().as_str_name())
-        .push_bind(reward.reward.oracle_boosted_hex_status().as_str_name());
+        .push_bind(reward.reward.oracle_boosted_hex_status().as_str_name())
+        .push_bind(&file_info.key)
+        .push_bind(file_info.timestamp);
     })
     .push("RETURNING id")
     .build()
     .fetch_all(tx)
     .await?;
 
-    Ok(rewards.into_iter()
+    Ok(rewards
+        .into_iter()
         .zip(ids)
-        .map(|(reward, row)| {
-            IdentifiedReward {
-                reward: reward.reward,
-                id: row.get(0),
-            }
+        .map(|(reward, row)| IdentifiedReward {
+            reward,
+            id: row.get(0),
         })
         .collect())
 }
@@ -111,65 +203,76 @@ async fn insert_reward(
 async fn insert_location_trust_scores(
     transaction: &mut Transaction<'_, Postgres>,
     identified_rewards: &[IdentifiedReward],
+    file_info: &FileInfo,
 ) -> anyhow::Result<()> {
+    let scores: Vec<_> = identified_rewards
+        .iter()
+        .flat_map(|ir| {
+            ir.reward
+                .reward
+                .location_trust_scores
+                .iter()
+                .zip(&ir.reward.trust_scores)
+                .map(|(lts, trust_score)| (ir.id, lts, trust_score))
+                .collect::<Vec<_>>()
+        })
+        .collect();
 
-    let scores : Vec<_> =
-        identified_rewards
-            .iter()
-            .flat_map(|ir| {
-                ir.reward.location_trust_scores.iter()
-                    .map(|lts| (ir.id, lts))
-                    .collect::<Vec<_>>()
-            })
-            .collect();
-
-    const NUM_IN_BATCH: usize = (u16::MAX / 3) as usize;
+    const NUM_IN_BATCH: usize = (u16::MAX / 5) as usize;
     for chunk in scores.chunks(NUM_IN_BATCH) {
         QueryBuilder::new(
             r#"
-            INSERT INTO location_trust_scores(id, meters_to_asserted, trust_score)
+            INSERT INTO location_trust_scores(id, meters_to_asserted, trust_score, file_key, file_timestamp)
             "#,
         )
-        .push_values(chunk, |mut b, (id, lt)| {
+        .push_values(chunk, |mut b, (id, lt, trust_score)| {
             b.push_bind(id)
                 .push_bind(lt.meters_to_asserted as i64)
-                .push_bind(super::from_proto_decimal(lt.trust_score.as_ref()));
+                .push_bind(*trust_score)
+                .push_bind(&file_info.key)
+                .push_bind(file_info.timestamp);
         })
         .build()
         .execute(&mut *transaction)
         .await?;
     }
-    
+
     Ok(())
 }
 
 async fn insert_speedtests(
     transaction: &mut Transaction<'_, Postgres>,
     identified_rewards: &[IdentifiedReward],
+    file_info: &FileInfo,
 ) -> anyhow::Result<()> {
-    let tests : Vec<_> =
-        identified_rewards
-            .iter()
-            .flat_map(|ir| {
-                ir.reward.speedtests.iter()
-                    .map(|st| (ir.id, st))
-                    .collect::<Vec<_>>()
-            })
-            .collect();
+    let tests: Vec<_> = identified_rewards
+        .iter()
+        .flat_map(|ir| {
+            ir.reward
+                .reward
+                .speedtests
+                .iter()
+                .zip(&ir.reward.speedtest_timestamps)
+                .map(|(st, timestamp)| (ir.id, st, timestamp))
+                .collect::<Vec<_>>()
+        })
+        .collect();
 
-    const NUM_IN_BATCH: usize = (u16::MAX / 5) as usize;
+    const NUM_IN_BATCH: usize = (u16::MAX / 7) as usize;
     for chunk in tests.chunks(NUM_IN_BATCH) {
         QueryBuilder::new(
             r#"
-            INSERT INTO speedtests(id, upload, download, latency, timestamp)
+            INSERT INTO radio_reward_speedtests(id, upload, download, latency, timestamp, file_key, file_timestamp)
             "#,
         )
-        .push_values(chunk, |mut b, (id, st)| {
+        .push_values(chunk, |mut b, (id, st, timestamp)| {
             b.push_bind(id)
                 .push_bind(st.upload_speed_bps as i64)
                 .push_bind(st.download_speed_bps as i64)
                 .push_bind(st.latency_ms as i32)
-                .push_bind(to_datetime(st.timestamp));
+                .push_bind(*timestamp)
+                .push_bind(&file_info.key)
+                .push_bind(file_info.timestamp);
         })
         .build()
         .execute(&mut *transaction)
@@ -181,28 +284,32 @@ async fn insert_speedtests(
 
 async fn insert_speedtest_averages(
     transaction: &mut Transaction<'_, Postgres>,
-    identified_rewards: &[IdentifiedReward]
+    identified_rewards: &[IdentifiedReward],
+    file_info: &FileInfo,
 ) -> anyhow::Result<()> {
+    let averages: Vec<_> = identified_rewards
+        .iter()
+        .filter_map(|ir| {
+            let sa = ir.reward.reward.speedtest_average.as_ref()?;
+            Some((ir.id, sa, ir.reward.speedtest_average_timestamp?))
+        })
+        .collect();
 
-    let averages: Vec<_> =
-        identified_rewards
-            .iter()
-            .filter_map(|ir| ir.reward.speedtest_average.as_ref().map(|sa| (ir.id, sa)))
-            .collect();
-
-    const NUM_IN_BATCH: usize = (u16::MAX / 5) as usize;
+    const NUM_IN_BATCH: usize = (u16::MAX / 7) as usize;
     for chunk in averages.chunks(NUM_IN_BATCH) {
         QueryBuilder::new(
             r#"
-            INSERT INTO speedtest_average(id, upload, download, latency, timestamp)
+            INSERT INTO radio_reward_speedtest_average(id, upload, download, latency, timestamp, file_key, file_timestamp)
             "#,
         )
-        .push_values(chunk, |mut b, (id, st)| {
+        .push_values(chunk, |mut b, (id, st, timestamp)| {
             b.push_bind(id)
                 .push_bind(st.upload_speed_bps as i64)
                 .push_bind(st.download_speed_bps as i64)
                 .push_bind(st.latency_ms as i32)
-                .push_bind(to_datetime(st.timestamp));
+                .push_bind(*timestamp)
+                .push_bind(&file_info.key)
+                .push_bind(file_info.timestamp);
         })
         .build()
         .execute(&mut *transaction)
@@ -215,41 +322,43 @@ async fn insert_speedtest_averages(
 async fn insert_covered_hexes(
     transaction: &mut Transaction<'_, Postgres>,
     identified_rewards: &[IdentifiedReward],
+    file_info: &FileInfo,
 ) -> anyhow::Result<()> {
+    let covered_hexes: Vec<_> = identified_rewards
+        .iter()
+        .flat_map(|ir| {
+            ir.reward
+                .reward
+                .covered_hexes
+                .iter()
+                .zip(&ir.reward.covered_hexes)
+                .map(|(ch, decimals)| (ir.id, ch, decimals))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    pg_copy::copy_in(
+        transaction,
+        "covered_hexes(id, location, base_coverage_points, boosted_coverage_points, urbanized, footfall, landtype, assignment_multiplier, rank, rank_multiplier, boosted_multiplier, file_key, file_timestamp)",
+        covered_hexes,
+        |mut row, (id, h, decimals)| {
+            row.push(id)
+                .push(h.location as i64)
+                .push(decimals.base_coverage_points)
+                .push(decimals.boosted_coverage_points)
+                .push(h.urbanized().as_str_name())
+                .push(h.footfall().as_str_name())
+                .push(h.landtype().as_str_name())
+                .push(decimals.assignment_multiplier)
+                .push(h.rank as i32)
+                .push(decimals.rank_multiplier)
+                .push(h.boosted_multiplier as i32)
+                .push(&file_info.key)
+                .push(file_info.timestamp);
+            Ok(())
+        },
+    )
+    .await?;
 
-    let covered_hexes: Vec<_> =
-        identified_rewards
-            .iter()
-            .flat_map(|ir| {
-                ir.reward.covered_hexes.iter()
-                    .map(|ch| (ir.id, ch))
-                    .collect::<Vec<_>>()
-            })
-            .collect();
-
-
-    const NUM_IN_BATCH: usize = (u16::MAX / 11) as usize;
-    for chunk in covered_hexes.chunks(NUM_IN_BATCH) {
-        QueryBuilder::new(r#"
-            INSERT INTO covered_hexes(id, location, base_coverage_points, boosted_coverage_points, urbanized, footfall, landtype, assignment_multiplier, rank, rank_multiplier, boosted_multiplier)
-            "#)
-            .push_values(chunk, |mut b, (id, h)| {
-                b.push_bind(id)
-                .push_bind(h.location as i64)
-                .push_bind(super::from_proto_decimal(h.base_coverage_points.as_ref()))
-                .push_bind(super::from_proto_decimal(h.boosted_coverage_points.as_ref()))
-                .push_bind(h.urbanized().as_str_name())
-                .push_bind(h.footfall().as_str_name())
-                .push_bind(h.landtype().as_str_name())
-                .push_bind(super::from_proto_decimal(h.assignment_multiplier.as_ref()))
-                .push_bind(h.rank as i32)
-                .push_bind(super::from_proto_decimal(h.rank_multiplier.as_ref()))
-                .push_bind(h.boosted_multiplier as i32);
-            })
-            .build()
-            .execute(&mut *transaction)
-            .await?;
-    }
-    
     Ok(())
 }
@@ -1,26 +1,24 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_proto::{services::poc_mobile::OracleBoostingReportV1, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 
-use crate::{to_datetime, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    pg_copy,
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeOracleBoostingReport {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeOracleBoostingReport {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                OracleBoostingReportV1::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, OracleBoostingReportV1::decode)
     }
 }
 
@@ -40,54 +38,126 @@ impl DbTable for FileTypeOracleBoostingReport {
                     timestamp timestamptz not null,
                     location bigint not null,
                     urbanized text not null,
-                    multiplier int not null
+                    footfall text,
+                    landtype text,
+                    service_provider_override text,
+                    multiplier int not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
         .execute(db)
+        .await?;
+
+        // the reward analyzer casts the stored assignments to it
+        sqlx::query(
+            r#"
+                DO $$ BEGIN
+                    CREATE TYPE oracle_assignment AS ENUM ('a', 'b', 'c');
+                EXCEPTION
+                    WHEN duplicate_object THEN null;
+                END $$
+            "#,
+        )
+        .execute(db)
         .await
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "oracle_boosting",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: format!(
+                "ALTER TABLE oracle_boosting ADD COLUMN IF NOT EXISTS footfall text, ADD COLUMN IF NOT EXISTS landtype text, ADD COLUMN IF NOT EXISTS service_provider_override text;\n{}",
+                provenance_migration(&["oracle_boosting"])
+            ),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "oracle_boosting_coverage_object_idx",
+            table: "oracle_boosting",
+            columns: "coverage_object",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<OracleBoostingReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 5) as usize;
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let reports = errors.rows(self, |report| {
+            let uuid = uuid::Uuid::from_slice(&report.coverage_object)
+                .map_err(RecordError::invalid("coverage_object"))?;
+            let timestamp = to_datetime(report.timestamp)?;
 
-        let rows: Vec<_> = self
-            .iter()
-            .flat_map(|report| {
-                let uuid = uuid::Uuid::from_slice(&report.coverage_object).unwrap();
-                let timestamp = to_datetime(report.timestamp);
+            report
+                .assignments
+                .iter()
+                .map(|assignment| {
+                    let location = i64::from_str_radix(&assignment.location, 16)
+                        .map_err(RecordError::invalid("location"))?;
+                    Ok((uuid, timestamp, location, assignment))
+                })
+                .collect::<Result<Vec<_>, RecordError>>()
+        })?;
+        let written = reports.len();
+        let rows: Vec<_> = reports.into_iter().flatten().collect();
 
-                report
-                    .assignments
-                    .iter()
-                    .map(move |assignment| (uuid, timestamp, assignment))
-            })
-            .collect();
+        pg_copy::copy_in(
+            transaction,
+            "oracle_boosting(coverage_object, timestamp, location, urbanized, footfall, landtype, service_provider_override, multiplier, file_key, file_timestamp)",
+            rows,
+            |mut row, (uuid, timestamp, location, hex)| {
+                row.push(uuid)
+                    .push(timestamp)
+                    .push(location)
+                    .push(hex.urbanized().as_str_name())
+                    .push(hex.footfall().as_str_name())
+                    .push(hex.landtype().as_str_name())
+                    .push(hex.service_provider_override().as_str_name())
+                    .push(hex.assignment_multiplier as i32)
+                    .push(&file_info.key)
+                    .push(file_info.timestamp);
+                Ok(())
+            },
+        )
+        .await?;
 
-        for chunk in rows.chunks(NUM_IN_BATCH) {
-            let mut qb = QueryBuilder::new("INSERT INTO oracle_boosting(coverage_object, timestamp, location, urbanized, multiplier)");
+        Ok(written)
+    }
+}
 
-            qb.push_values(chunk, |mut b, (uuid, timestamp, hex)| {
-                b.push_bind(uuid)
-                    .push_bind(timestamp)
-                    .push_bind(i64::from_str_radix(&hex.location, 16).unwrap())
-                    .push_bind(hex.urbanized().as_str_name())
-                    .push_bind(hex.assignment_multiplier as i32);
-            })
-            .build()
-            .execute(db)
-            .await?;
-        }
+impl Record for OracleBoostingReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime(self.timestamp).ok()
+    }
 
-        Ok(())
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "coverage_object": uuid::Uuid::from_slice(&self.coverage_object).ok(),
+            "timestamp": to_datetime(self.timestamp).ok(),
+            "assignments": self.assignments.iter().map(|hex| json!({
+                "location": hex.location,
+                "urbanized": hex.urbanized().as_str_name(),
+                "footfall": hex.footfall().as_str_name(),
+                "landtype": hex.landtype().as_str_name(),
+                "service_provider_override": hex.service_provider_override().as_str_name(),
+                "multiplier": hex.assignment_multiplier,
+            })).collect::<Vec<_>>(),
+        })
     }
 }
@@ -0,0 +1,191 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use clap::ValueEnum;
+use sqlx::{Pool, Postgres, Transaction};
+
+use crate::SupportedFileTypes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PartitionBy {
+    Day,
+    Epoch,
+}
+
+// A table that can be created range partitioned on one of its timestamp columns instead
+// of as a plain table. `columns` is the column list `create_table` uses for it.
+#[derive(Debug, Clone, Copy)]
+pub struct Partitioned {
+    pub table: &'static str,
+    pub column: &'static str,
+    pub columns: &'static str,
+}
+
+pub async fn create_table(db: &Pool<Postgres>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS partitioned_tables (
+                table_name text primary key,
+                partition_by text not null
+            )
+        "#,
+    )
+    .execute(db)
+    .await
+    .map(|_| ())
+    .map_err(anyhow::Error::from)
+}
+
+// Creates the file type's partitionable tables as partitioned tables. Must run before the
+// file type's `create_table`, tables that already exist are left as they are.
+pub async fn create_tables(
+    db: &Pool<Postgres>,
+    file_type: &SupportedFileTypes,
+    partition_by: PartitionBy,
+) -> anyhow::Result<()> {
+    for partitioned in file_type.partitioned() {
+        let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+            .bind(partitioned.table)
+            .fetch_one(db)
+            .await?;
+        if exists {
+            continue;
+        }
+
+        let mut transaction = db.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE {} ({}) PARTITION BY RANGE ({})",
+            partitioned.table, partitioned.columns, partitioned.column
+        ))
+        .execute(&mut transaction)
+        .await?;
+        sqlx::query("INSERT INTO partitioned_tables(table_name, partition_by) VALUES($1, $2)")
+            .bind(partitioned.table)
+            .bind(name(partition_by))
+            .execute(&mut transaction)
+            .await?;
+        transaction.commit().await?;
+
+        println!(
+            "created {} partitioned by {}",
+            partitioned.table,
+            name(partition_by)
+        );
+    }
+
+    Ok(())
+}
+
+// Makes sure a partition exists for every (table, timestamp) about to be inserted. Each
+// partition is created and attached in its own short transaction on the pool and committed
+// before the batch is inserted, so concurrent imports only wait on each other for the
+// moment it takes to attach one partition rather than for a whole file's import. ATTACH
+// PARTITION takes a SHARE UPDATE EXCLUSIVE lock on the parent, which doesn't conflict
+// with the ROW EXCLUSIVE locks open import transactions hold on it. A failed import
+// leaves the partitions it created behind, empty.
+pub async fn ensure(
+    db: &Pool<Postgres>,
+    keys: Vec<(&'static str, DateTime<Utc>)>,
+) -> anyhow::Result<()> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let tables: BTreeSet<&str> = keys.iter().map(|(table, _)| *table).collect();
+    let partitioned: HashMap<String, String> = sqlx::query_as(
+        "SELECT table_name, partition_by FROM partitioned_tables WHERE table_name = ANY($1)",
+    )
+    .bind(tables.into_iter().collect::<Vec<_>>())
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .collect();
+    if partitioned.is_empty() {
+        return Ok(());
+    }
+
+    let mut bounds: BTreeSet<(&str, String, DateTime<Utc>, DateTime<Utc>)> = BTreeSet::new();
+    for (table, timestamp) in keys {
+        let Some(partition_by) = partitioned.get(table) else {
+            continue;
+        };
+
+        let start = timestamp.duration_trunc(Duration::days(1))?;
+        let suffix = match PartitionBy::from_str(partition_by, true).map_err(anyhow::Error::msg)? {
+            PartitionBy::Day => start.format("%Y%m%d").to_string(),
+            // mobile reward epochs are fixed 24h UTC windows numbered by the days since the
+            // unix epoch, so they can be computed before the epoch's reward manifest exists
+            PartitionBy::Epoch => format!("epoch_{}", start.timestamp() / SECONDS_PER_DAY),
+        };
+        bounds.insert((table, suffix, start, start + Duration::days(1)));
+    }
+
+    for (table, suffix, start, end) in bounds {
+        attach(db, table, &suffix, start, end).await?;
+    }
+
+    Ok(())
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+async fn attach(
+    db: &Pool<Postgres>,
+    table: &str,
+    suffix: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let partition = format!("{}_{}", table, suffix);
+    let mut transaction = db.begin().await?;
+    if partition_exists(&mut transaction, &partition).await? {
+        return Ok(());
+    }
+
+    // serializes concurrent imports trying to create the same partition, held until this
+    // transaction commits
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(&partition)
+        .execute(&mut transaction)
+        .await?;
+    if partition_exists(&mut transaction, &partition).await? {
+        return Ok(());
+    }
+
+    sqlx::query(&format!(
+        "CREATE TABLE {} (LIKE {} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)",
+        partition, table
+    ))
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query(&format!(
+        "ALTER TABLE {} ATTACH PARTITION {} FOR VALUES FROM ('{}') TO ('{}')",
+        table,
+        partition,
+        start.to_rfc3339(),
+        end.to_rfc3339()
+    ))
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+async fn partition_exists(
+    transaction: &mut Transaction<'_, Postgres>,
+    partition: &str,
+) -> anyhow::Result<bool> {
+    sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+        .bind(partition)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(anyhow::Error::from)
+}
+
+fn name(partition_by: PartitionBy) -> String {
+    partition_by
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
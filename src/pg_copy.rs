@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+const SEND_BUFFER_SIZE: usize = 1024 * 1024;
+
+pub trait CsvValue {
+    fn write_csv(&self, buf: &mut Vec<u8>);
+}
+
+fn write_quoted(buf: &mut Vec<u8>, value: &str) {
+    buf.push(b'"');
+    for byte in value.bytes() {
+        if byte == b'"' {
+            buf.push(b'"');
+        }
+        buf.push(byte);
+    }
+    buf.push(b'"');
+}
+
+impl CsvValue for str {
+    fn write_csv(&self, buf: &mut Vec<u8>) {
+        write_quoted(buf, self);
+    }
+}
+
+impl CsvValue for String {
+    fn write_csv(&self, buf: &mut Vec<u8>) {
+        write_quoted(buf, self);
+    }
+}
+
+impl CsvValue for DateTime<Utc> {
+    fn write_csv(&self, buf: &mut Vec<u8>) {
+        write_quoted(buf, &self.to_rfc3339());
+    }
+}
+
+impl CsvValue for Uuid {
+    fn write_csv(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.to_string().as_bytes());
+    }
+}
+
+macro_rules! impl_csv_value_display {
+    ($($t:ty),*) => {
+        $(
+            impl CsvValue for $t {
+                fn write_csv(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(self.to_string().as_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_csv_value_display!(bool, i32, i64, f64, Decimal);
+
+impl<T: CsvValue + ?Sized> CsvValue for &T {
+    fn write_csv(&self, buf: &mut Vec<u8>) {
+        (**self).write_csv(buf);
+    }
+}
+
+impl<T: CsvValue> CsvValue for Option<T> {
+    fn write_csv(&self, buf: &mut Vec<u8>) {
+        // an unquoted empty field is read by COPY as NULL
+        if let Some(value) = self {
+            value.write_csv(buf);
+        }
+    }
+}
+
+pub struct CsvRow<'a> {
+    buf: &'a mut Vec<u8>,
+    first: bool,
+}
+
+impl CsvRow<'_> {
+    pub fn push(&mut self, value: impl CsvValue) -> &mut Self {
+        if !self.first {
+            self.buf.push(b',');
+        }
+        self.first = false;
+        value.write_csv(self.buf);
+        self
+    }
+}
+
+// Streams rows into `table` with `COPY ... FROM STDIN WITH (FORMAT csv)`,
+// mirroring QueryBuilder::push_values so callers build one row per item.
+pub async fn copy_in<T>(
+    transaction: &mut Transaction<'_, Postgres>,
+    table: &str,
+    rows: impl IntoIterator<Item = T>,
+    mut push_row: impl FnMut(CsvRow<'_>, T) -> anyhow::Result<()>,
+) -> anyhow::Result<u64> {
+    let statement = format!("COPY {} FROM STDIN WITH (FORMAT csv)", table);
+    let mut copy = transaction.copy_in_raw(&statement).await?;
+    let mut buf = Vec::with_capacity(SEND_BUFFER_SIZE);
+
+    for row in rows {
+        push_row(
+            CsvRow {
+                buf: &mut buf,
+                first: true,
+            },
+            row,
+        )?;
+        buf.push(b'\n');
+
+        if buf.len() >= SEND_BUFFER_SIZE {
+            copy.send(buf.as_slice()).await?;
+            buf.clear();
+        }
+    }
+
+    if !buf.is_empty() {
+        copy.send(buf.as_slice()).await?;
+    }
+
+    copy.finish().await.map_err(anyhow::Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv(push: impl FnOnce(&mut CsvRow<'_>)) -> String {
+        let mut buf = Vec::new();
+        push(&mut CsvRow {
+            buf: &mut buf,
+            first: true,
+        });
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn field(value: impl CsvValue) -> String {
+        csv(|row| {
+            row.push(value);
+        })
+    }
+
+    #[test]
+    fn quotes_and_escapes_text() {
+        assert_eq!(field("plain"), r#""plain""#);
+        assert_eq!(field(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(field("a,b\nc"), "\"a,b\nc\"");
+    }
+
+    #[test]
+    fn empty_text_is_not_null() {
+        assert_eq!(field(""), r#""""#);
+        assert_eq!(field(None::<&str>), "");
+    }
+
+    #[test]
+    fn separates_columns() {
+        assert_eq!(
+            csv(|row| {
+                row.push(1_i64)
+                    .push(None::<i32>)
+                    .push(true)
+                    .push(Decimal::new(15, 1))
+                    .push(Some("x"));
+            }),
+            r#"1,,true,1.5,"x""#
+        );
+    }
+}
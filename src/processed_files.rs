@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use file_store::FileInfo;
+use sqlx::{Pool, Postgres, Transaction};
+
+pub async fn create_table(db: &Pool<Postgres>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS processed_files (
+                file_key text not null,
+                file_type text not null,
+                file_timestamp timestamptz not null,
+                row_count bigint not null,
+                completed_at timestamptz not null,
+                primary key (file_key, file_type)
+            )
+        "#,
+    )
+    .execute(db)
+    .await
+    .map(|_| ())
+    .map_err(anyhow::Error::from)
+}
+
+pub async fn processed_keys(
+    db: &Pool<Postgres>,
+    file_type: &str,
+) -> anyhow::Result<HashSet<String>> {
+    let keys = sqlx::query_scalar::<_, String>(
+        r#"
+            SELECT file_key FROM processed_files WHERE file_type = $1
+        "#,
+    )
+    .bind(file_type)
+    .fetch_all(db)
+    .await?;
+
+    Ok(keys.into_iter().collect())
+}
+
+pub async fn record(
+    transaction: &mut Transaction<'_, Postgres>,
+    file_type: &str,
+    file_info: &FileInfo,
+    row_count: usize,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+            INSERT INTO processed_files(file_key, file_type, file_timestamp, row_count, completed_at)
+            VALUES($1, $2, $3, $4, $5)
+            ON CONFLICT (file_key, file_type) DO UPDATE SET
+                file_timestamp = EXCLUDED.file_timestamp,
+                row_count = EXCLUDED.row_count,
+                completed_at = EXCLUDED.completed_at
+        "#,
+    )
+    .bind(&file_info.key)
+    .bind(file_type)
+    .bind(file_info.timestamp)
+    .bind(row_count as i64)
+    .bind(Utc::now())
+    .execute(transaction)
+    .await
+    .map(|_| ())
+    .map_err(anyhow::Error::from)
+}
+
+const WINDOW: &str = r#"
+    file_type = $1
+    AND ($2::timestamptz IS NULL OR file_timestamp >= $2)
+    AND ($3::timestamptz IS NULL OR file_timestamp < $3)
+"#;
+
+pub async fn count(
+    db: &Pool<Postgres>,
+    file_type: &str,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+) -> anyhow::Result<i64> {
+    sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM processed_files WHERE {WINDOW}"
+    ))
+    .bind(file_type)
+    .bind(after)
+    .bind(before)
+    .fetch_one(db)
+    .await
+    .map_err(anyhow::Error::from)
+}
+
+pub async fn delete(
+    transaction: &mut Transaction<'_, Postgres>,
+    file_type: &str,
+    after: Option<DateTime<Utc>>,
+    before: Option<DateTime<Utc>>,
+) -> anyhow::Result<u64> {
+    sqlx::query(&format!("DELETE FROM processed_files WHERE {WINDOW}"))
+        .bind(file_type)
+        .bind(after)
+        .bind(before)
+        .execute(transaction)
+        .await
+        .map(|result| result.rows_affected())
+        .map_err(anyhow::Error::from)
+}
+
+pub async fn count_file(
+    db: &Pool<Postgres>,
+    file_type: &str,
+    file_key: &str,
+) -> anyhow::Result<i64> {
+    sqlx::query_scalar(
+        "SELECT count(*) FROM processed_files WHERE file_type = $1 AND file_key = $2",
+    )
+    .bind(file_type)
+    .bind(file_key)
+    .fetch_one(db)
+    .await
+    .map_err(anyhow::Error::from)
+}
+
+pub async fn delete_file(
+    transaction: &mut Transaction<'_, Postgres>,
+    file_type: &str,
+    file_key: &str,
+) -> anyhow::Result<u64> {
+    sqlx::query("DELETE FROM processed_files WHERE file_type = $1 AND file_key = $2")
+        .bind(file_type)
+        .bind(file_key)
+        .execute(transaction)
+        .await
+        .map(|result| result.rows_affected())
+        .map_err(anyhow::Error::from)
+}
@@ -1,32 +1,34 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
-use helium_crypto::PublicKey;
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::{PublicKey, PublicKeyBinary};
 use helium_proto::{
     services::poc_mobile::{
         VerifiedInvalidatedRadioThresholdIngestReportV1, VerifiedRadioThresholdIngestReportV1,
     },
     Message,
 };
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{to_datetime, to_datetime_ms, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, to_datetime_ms, CleanTarget, DbTable, Decode, Insertable, InsertableStream,
+    Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeRadioThreshold {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeRadioThreshold {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                VerifiedRadioThresholdIngestReportV1::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(
+            stream,
+            keep_raw,
+            VerifiedRadioThresholdIngestReportV1::decode,
+        )
     }
 }
 
@@ -47,7 +49,9 @@ impl DbTable for FileTypeRadioThreshold {
                     hotspot_key text not null,
                     cbsd_id text,
                     validated bool not null,
-                    threshold_timestamp timestamptz
+                    threshold_timestamp timestamptz,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -56,60 +60,99 @@ impl DbTable for FileTypeRadioThreshold {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::FilteredTable {
+            table: "radio_thresholds",
+            condition: "validated",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["radio_thresholds"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "radio_thresholds_hotspot_key_idx",
+            table: "radio_thresholds",
+            columns: "hotspot_key, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<VerifiedRadioThresholdIngestReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 6) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            let mut qb = QueryBuilder::new("INSERT INTO radio_thresholds(received_timestamp, status, hotspot_key, cbsd_id, validated, threshold_timestamp)");
-
-            qb.push_values(chunk, |mut b, top_report| {
-                let report = top_report.clone().report.unwrap().report.unwrap();
-                let pubkey = PublicKey::try_from(report.hotspot_pubkey)
-                    .unwrap()
-                    .to_string();
-
-                b.push_bind(to_datetime_ms(
-                    top_report.clone().report.unwrap().received_timestamp,
-                ))
-                .push_bind(top_report.status().as_str_name())
-                .push_bind(pubkey)
-                .push_bind(report.cbsd_id)
-                .push_bind(true)
-                .push_bind(to_datetime(report.threshold_timestamp));
-            })
-            .build()
-            .execute(db)
-            .await?;
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 8) as usize;
+
+        let rows = errors.rows(self, |top_report| {
+            let ingest = top_report
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let report = ingest
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report.report"))?;
+            let pubkey = PublicKey::try_from(report.hotspot_pubkey.clone())
+                .map_err(RecordError::invalid("hotspot_pubkey"))?
+                .to_string();
+            Ok((
+                top_report,
+                report,
+                pubkey,
+                to_datetime_ms(ingest.received_timestamp)?,
+                to_datetime(report.threshold_timestamp)?,
+            ))
+        })?;
+
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            let mut qb = QueryBuilder::new("INSERT INTO radio_thresholds(received_timestamp, status, hotspot_key, cbsd_id, validated, threshold_timestamp, file_key, file_timestamp)");
+
+            written += qb
+                .push_values(
+                    chunk,
+                    |mut b, (top_report, report, pubkey, received_timestamp, timestamp)| {
+                        b.push_bind(*received_timestamp)
+                            .push_bind(top_report.status().as_str_name())
+                            .push_bind(pubkey.clone())
+                            .push_bind(report.cbsd_id.clone())
+                            .push_bind(true)
+                            .push_bind(*timestamp)
+                            .push_bind(&file_info.key)
+                            .push_bind(file_info.timestamp);
+                    },
+                )
+                .build()
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
         }
 
-        Ok(())
+        Ok(written as usize)
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct FileTypeInvalidatedRadioThreshold {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeInvalidatedRadioThreshold {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                VerifiedInvalidatedRadioThresholdIngestReportV1::decode(buf)
-                    .map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(
+            stream,
+            keep_raw,
+            VerifiedInvalidatedRadioThresholdIngestReportV1::decode,
+        )
     }
 }
 
@@ -124,13 +167,15 @@ impl DbTable for FileTypeInvalidatedRadioThreshold {
     async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
         sqlx::query(
             r#"
-                CREATE TABLE IF NOT EXISTS radio_thresholds (
+                CREATE TABLE IF NOT EXISTS invalidated_radio_thresholds (
                     received_timestamp timestamptz not null,
                     status text not null,
                     hotspot_key text not null,
                     cbsd_id text,
-                    validated bool not null,
-                    threshold_timestamp timestamptz
+                    reason text,
+                    invalidated_timestamp timestamptz,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -139,40 +184,148 @@ impl DbTable for FileTypeInvalidatedRadioThreshold {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "invalidated_radio_thresholds",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        // invalidations used to be written to radio_thresholds with validated = false,
+        // without their reason
+        vec![
+            Migration {
+                version: 1,
+                sql: r#"
+                DO $$
+                BEGIN
+                    IF to_regclass('radio_thresholds') IS NOT NULL THEN
+                        INSERT INTO invalidated_radio_thresholds(received_timestamp, status, hotspot_key, cbsd_id, invalidated_timestamp)
+                        SELECT received_timestamp, status, hotspot_key, cbsd_id, threshold_timestamp
+                        FROM radio_thresholds
+                        WHERE NOT validated;
+
+                        DELETE FROM radio_thresholds WHERE NOT validated;
+                    END IF;
+                END
+                $$
+            "#.to_string(),
+            },
+            Migration {
+                version: 2,
+                sql: provenance_migration(&["invalidated_radio_thresholds"]),
+            },
+        ]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "invalidated_radio_thresholds_hotspot_key_idx",
+            table: "invalidated_radio_thresholds",
+            columns: "hotspot_key, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<VerifiedInvalidatedRadioThresholdIngestReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 6) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            let mut qb = QueryBuilder::new("INSERT INTO radio_thresholds(received_timestamp, status, hotspot_key, cbsd_id, validated, threshold_timestamp)");
-
-            qb.push_values(chunk, |mut b, top_report| {
-                let report = top_report.clone().report.unwrap().report.unwrap();
-                let pubkey = PublicKey::try_from(report.hotspot_pubkey)
-                    .unwrap()
-                    .to_string();
-
-                b.push_bind(to_datetime_ms(
-                    top_report.clone().report.unwrap().received_timestamp,
-                ))
-                .push_bind(top_report.status().as_str_name())
-                .push_bind(pubkey)
-                .push_bind(report.cbsd_id)
-                .push_bind(false)
-                .push_bind(to_datetime(report.timestamp));
-            })
-            .build()
-            .execute(db)
-            .await?;
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 8) as usize;
+
+        let rows = errors.rows(self, |top_report| {
+            let ingest = top_report
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let report = ingest
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report.report"))?;
+            let pubkey = PublicKey::try_from(report.hotspot_pubkey.clone())
+                .map_err(RecordError::invalid("hotspot_pubkey"))?
+                .to_string();
+            Ok((
+                top_report,
+                report,
+                pubkey,
+                to_datetime_ms(ingest.received_timestamp)?,
+                to_datetime(report.timestamp)?,
+            ))
+        })?;
+
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            let mut qb = QueryBuilder::new("INSERT INTO invalidated_radio_thresholds(received_timestamp, status, hotspot_key, cbsd_id, reason, invalidated_timestamp, file_key, file_timestamp)");
+
+            written += qb
+                .push_values(
+                    chunk,
+                    |mut b, (top_report, report, pubkey, received_timestamp, timestamp)| {
+                        b.push_bind(*received_timestamp)
+                            .push_bind(top_report.status().as_str_name())
+                            .push_bind(pubkey.clone())
+                            .push_bind(report.cbsd_id.clone())
+                            .push_bind(report.reason().as_str_name())
+                            .push_bind(*timestamp)
+                            .push_bind(&file_info.key)
+                            .push_bind(file_info.timestamp);
+                    },
+                )
+                .build()
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
         }
 
-        Ok(())
+        Ok(written as usize)
+    }
+}
+
+impl Record for VerifiedRadioThresholdIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.report
+            .as_ref()
+            .and_then(|r| to_datetime_ms(r.received_timestamp).ok())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let ingest = self.report.as_ref();
+        let report = ingest.and_then(|r| r.report.as_ref());
+
+        json!({
+            "status": self.status().as_str_name(),
+            "received_timestamp": ingest.and_then(|r| to_datetime_ms(r.received_timestamp).ok()),
+            "hotspot_key": report.map(|r| PublicKeyBinary::from(r.hotspot_pubkey.clone()).to_string()),
+            "cbsd_id": report.map(|r| &r.cbsd_id),
+            "threshold_timestamp": report.and_then(|r| to_datetime(r.threshold_timestamp).ok()),
+        })
+    }
+}
+
+impl Record for VerifiedInvalidatedRadioThresholdIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.report
+            .as_ref()
+            .and_then(|r| to_datetime_ms(r.received_timestamp).ok())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let ingest = self.report.as_ref();
+        let report = ingest.and_then(|r| r.report.as_ref());
+
+        json!({
+            "status": self.status().as_str_name(),
+            "received_timestamp": ingest.and_then(|r| to_datetime_ms(r.received_timestamp).ok()),
+            "hotspot_key": report.map(|r| PublicKeyBinary::from(r.hotspot_pubkey.clone()).to_string()),
+            "cbsd_id": report.map(|r| &r.cbsd_id),
+            "reason": report.map(|r| r.reason().as_str_name()),
+            "timestamp": report.and_then(|r| to_datetime(r.timestamp).ok()),
+        })
     }
 }
@@ -1,27 +1,24 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{services::poc_mobile::RadioUsageStatsIngestReportV1, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{determine_timestamp, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches, determine_timestamp,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeRadioUsageStatsIngestReport;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeRadioUsageStatsIngestReport {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                RadioUsageStatsIngestReportV1::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, RadioUsageStatsIngestReportV1::decode)
     }
 }
 
@@ -47,7 +44,9 @@ impl DbTable for FileTypeRadioUsageStatsIngestReport {
                     offload_transfer_bytes bigint not null,
                     epoch_start timestamptz not null,
                     epoch_end timestamptz not null,
-                    generated_timestamp timestamptz not null
+                    generated_timestamp timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -56,42 +55,102 @@ impl DbTable for FileTypeRadioUsageStatsIngestReport {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "radio_usage_stats_ingest",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["radio_usage_stats_ingest"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "radio_usage_stats_ingest_pubkey_idx",
+            table: "radio_usage_stats_ingest",
+            columns: "pubkey, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<RadioUsageStatsIngestReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 11) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO radio_usage_stats_ingest(received_timestamp, pubkey, cbsd_id, service_provider_user_count, disco_mapping_user_count, offload_user_count, service_provider_transfer_bytes, offload_transfer_bytes, epoch_start, epoch_end, generated_timestamp)")
-            .push_values(chunk, |mut b, report| {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 13) as usize;
 
-                let req = report.clone().report.unwrap();
+        let rows = errors.rows(self, |report| {
+            let req = report
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            Ok((
+                req,
+                determine_timestamp(report.received_timestamp)?,
+                determine_timestamp(req.epoch_start_timestamp)?,
+                determine_timestamp(req.epoch_end_timestamp)?,
+                determine_timestamp(req.timestamp)?,
+            ))
+        })?;
 
-                b.push_bind(determine_timestamp(report.received_timestamp))
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO radio_usage_stats_ingest(received_timestamp, pubkey, cbsd_id, service_provider_user_count, disco_mapping_user_count, offload_user_count, service_provider_transfer_bytes, offload_transfer_bytes, epoch_start, epoch_end, generated_timestamp, file_key, file_timestamp)")
+            .push_values(chunk, |mut b, (req, received_timestamp, epoch_start, epoch_end, timestamp)| {
+                b.push_bind(*received_timestamp)
                     .push_bind(PublicKeyBinary::from(req.hotspot_pubkey.clone()).to_string())
-                    .push_bind(req.cbsd_id)
+                    .push_bind(req.cbsd_id.clone())
                     .push_bind(req.service_provider_user_count as i64)
                     .push_bind(req.disco_mapping_user_count as i64)
                     .push_bind(req.offload_user_count as i64)
                     .push_bind(req.service_provider_transfer_bytes as i64)
                     .push_bind(req.offload_transfer_bytes as i64)
-                    .push_bind(determine_timestamp(req.epoch_start_timestamp))
-                    .push_bind(determine_timestamp(req.epoch_end_timestamp))
-                    .push_bind(determine_timestamp(req.timestamp));
-                
+                    .push_bind(*epoch_start)
+                    .push_bind(*epoch_end)
+                    .push_bind(*timestamp)
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
+
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
 
-        Ok(())
+        Ok(written as usize)
     }
 }
 
+impl Record for RadioUsageStatsIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        determine_timestamp(self.received_timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let req = self.report.as_ref();
+
+        json!({
+            "received_timestamp": determine_timestamp(self.received_timestamp).ok(),
+            "pubkey": req.map(|r| PublicKeyBinary::from(r.hotspot_pubkey.clone()).to_string()),
+            "cbsd_id": req.map(|r| &r.cbsd_id),
+            "service_provider_user_count": req.map(|r| r.service_provider_user_count),
+            "disco_mapping_user_count": req.map(|r| r.disco_mapping_user_count),
+            "offload_user_count": req.map(|r| r.offload_user_count),
+            "service_provider_transfer_bytes": req.map(|r| r.service_provider_transfer_bytes),
+            "offload_transfer_bytes": req.map(|r| r.offload_transfer_bytes),
+            "epoch_start": req.and_then(|r| determine_timestamp(r.epoch_start_timestamp).ok()),
+            "epoch_end": req.and_then(|r| determine_timestamp(r.epoch_end_timestamp).ok()),
+            "generated_timestamp": req.and_then(|r| determine_timestamp(r.timestamp).ok()),
+        })
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+
+use bytes::Bytes;
+use chrono::Utc;
+use file_store::FileInfo;
+use sqlx::{Pool, Postgres, Transaction};
+
+#[derive(Debug)]
+pub enum RecordError {
+    MissingField(&'static str),
+    InvalidField(&'static str, String),
+    InvalidTimestamp(u64),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::MissingField(field) => write!(f, "missing field: {}", field),
+            RecordError::InvalidField(field, reason) => {
+                write!(f, "invalid field: {}, {}", field, reason)
+            }
+            RecordError::InvalidTimestamp(timestamp) => {
+                write!(f, "invalid timestamp: {}", timestamp)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl RecordError {
+    pub fn invalid<E: fmt::Display>(field: &'static str) -> impl FnOnce(E) -> Self {
+        move |err| RecordError::InvalidField(field, err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorPolicy {
+    Fail,
+    Skip,
+    Quarantine,
+}
+
+#[derive(Debug, Clone)]
+pub struct RawRecord {
+    // offset of the record's length prefix in the decompressed file
+    pub offset: u64,
+    // empty unless the import quarantines bad records
+    pub bytes: Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub raw: RawRecord,
+    pub error: String,
+}
+
+pub struct RecordErrors {
+    policy: ErrorPolicy,
+    count: usize,
+    // indexes into the batch currently being inserted, resolved to raw bytes by `dead_letter_pending`
+    pending: Vec<(usize, String)>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+impl RecordErrors {
+    pub fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            count: 0,
+            pending: vec![],
+            dead_letters: vec![],
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // Maps every record to a row, applying the error policy to the ones that fail.
+    pub fn rows<'a, T, R>(
+        &mut self,
+        records: &'a [T],
+        to_row: impl Fn(&'a T) -> Result<R, RecordError>,
+    ) -> anyhow::Result<Vec<R>> {
+        let mut rows = Vec::with_capacity(records.len());
+        for (index, record) in records.iter().enumerate() {
+            match to_row(record) {
+                Ok(row) => rows.push(row),
+                Err(err) => self.push(index, err)?,
+            }
+        }
+        Ok(rows)
+    }
+
+    pub fn push(&mut self, index: usize, err: RecordError) -> anyhow::Result<()> {
+        match self.policy {
+            ErrorPolicy::Fail => return Err(anyhow::Error::from(err)),
+            ErrorPolicy::Skip => (),
+            ErrorPolicy::Quarantine => self.pending.push((index, err.to_string())),
+        }
+
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn undecodable(&mut self, dead_letter: &DeadLetter) -> anyhow::Result<()> {
+        match self.policy {
+            ErrorPolicy::Fail => anyhow::bail!(
+                "undecodable record at offset {}: {}",
+                dead_letter.raw.offset,
+                dead_letter.error
+            ),
+            ErrorPolicy::Skip => (),
+            ErrorPolicy::Quarantine => self.dead_letters.push(dead_letter.clone()),
+        }
+
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn dead_letter_pending(&mut self, raw: &[RawRecord]) {
+        for (index, error) in self.pending.drain(..) {
+            self.dead_letters.push(DeadLetter {
+                raw: raw[index].clone(),
+                error,
+            });
+        }
+    }
+
+    pub async fn write_dead_letters(
+        &mut self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_type: &str,
+        file_info: &FileInfo,
+    ) -> anyhow::Result<()> {
+        for dead_letter in self.dead_letters.drain(..) {
+            sqlx::query(
+                r#"
+                    INSERT INTO dead_letters(file_key, file_type, byte_offset, error, raw, created_at)
+                    VALUES($1, $2, $3, $4, $5, $6)
+                "#,
+            )
+            .bind(&file_info.key)
+            .bind(file_type)
+            .bind(dead_letter.raw.offset as i64)
+            .bind(dead_letter.error)
+            .bind(dead_letter.raw.bytes.as_ref())
+            .bind(Utc::now())
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn create_table(db: &Pool<Postgres>) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS dead_letters (
+                file_key text not null,
+                file_type text not null,
+                byte_offset bigint not null,
+                error text not null,
+                raw bytea not null,
+                created_at timestamptz not null
+            )
+        "#,
+    )
+    .execute(db)
+    .await
+    .map(|_| ())
+    .map_err(anyhow::Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positive(value: &i64) -> Result<i64, RecordError> {
+        if *value > 0 {
+            Ok(*value)
+        } else {
+            Err(RecordError::InvalidField("value", value.to_string()))
+        }
+    }
+
+    fn raw(offset: u64) -> RawRecord {
+        RawRecord {
+            offset,
+            bytes: Bytes::from(offset.to_string()),
+        }
+    }
+
+    fn dead_letter(offset: u64) -> DeadLetter {
+        DeadLetter {
+            raw: raw(offset),
+            error: "undecodable".to_string(),
+        }
+    }
+
+    #[test]
+    fn fail_stops_at_the_first_bad_record() {
+        let mut errors = RecordErrors::new(ErrorPolicy::Fail);
+        let err = errors.rows(&[1, -2, 3, -4], positive).unwrap_err();
+
+        assert_eq!(err.to_string(), "invalid field: value, -2");
+        assert!(errors.undecodable(&dead_letter(0)).is_err());
+    }
+
+    #[test]
+    fn skip_counts_bad_records() -> anyhow::Result<()> {
+        let mut errors = RecordErrors::new(ErrorPolicy::Skip);
+        assert_eq!(errors.rows(&[1, -2, 3, -4], positive)?, vec![1, 3]);
+        errors.undecodable(&dead_letter(40))?;
+        errors.dead_letter_pending(&[raw(0), raw(10), raw(20), raw(30)]);
+
+        assert_eq!(errors.count(), 3);
+        assert!(errors.dead_letters.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn quarantine_keeps_bad_records() -> anyhow::Result<()> {
+        let mut errors = RecordErrors::new(ErrorPolicy::Quarantine);
+        assert_eq!(errors.rows(&[1, -2, 3, -4], positive)?, vec![1, 3]);
+        errors.dead_letter_pending(&[raw(0), raw(10), raw(20), raw(30)]);
+        errors.undecodable(&dead_letter(40))?;
+
+        assert_eq!(errors.count(), 3);
+        assert_eq!(
+            errors
+                .dead_letters
+                .iter()
+                .map(|dead_letter| (
+                    dead_letter.raw.offset,
+                    dead_letter.raw.bytes.clone(),
+                    dead_letter.error.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (10, Bytes::from("10"), "invalid field: value, -2"),
+                (30, Bytes::from("30"), "invalid field: value, -4"),
+                (40, Bytes::from("40"), "undecodable"),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -1,24 +1,22 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_proto::{reward_manifest::RewardData, Message, RewardManifest};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{to_datetime, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeRewardManifest;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeRewardManifest {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move { RewardManifest::decode(buf).map_err(anyhow::Error::from) })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, RewardManifest::decode)
     }
 }
 
@@ -38,7 +36,9 @@ impl DbTable for FileTypeRewardManifest {
                     end_timestamp TIMESTAMPTZ NOT NULL,
                     epoch BIGINT NOT NULL,
                     price BIGINT NOT NULL,
-                    token TEXT NOT NULL
+                    token TEXT NOT NULL,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -47,38 +47,87 @@ impl DbTable for FileTypeRewardManifest {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "reward_manifests",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["reward_manifests"]),
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<RewardManifest> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 5) as usize;
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 7) as usize;
 
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new(
-                "INSERT INTO reward_manifests(start_timestamp, end_timestamp, epoch, price, token)",
-            )
-            .push_values(chunk, |mut b, report| {
-                let token = match &report.reward_data {
-                    Some(RewardData::MobileRewardData(mobile)) => mobile.token().as_str_name(),
-                    Some(RewardData::IotRewardData(iot)) => iot.token().as_str_name(),
-                    _ => panic!("Unknown reward data"),
-                };
+        let rows = errors.rows(self, |report| {
+            let token = match &report.reward_data {
+                Some(RewardData::MobileRewardData(mobile)) => mobile.token().as_str_name(),
+                Some(RewardData::IotRewardData(iot)) => iot.token().as_str_name(),
+                None => return Err(RecordError::MissingField("reward_data")),
+            };
+            Ok((
+                report,
+                token,
+                to_datetime(report.start_timestamp)?,
+                to_datetime(report.end_timestamp)?,
+            ))
+        })?;
 
-                b.push_bind(to_datetime(report.start_timestamp))
-                    .push_bind(to_datetime(report.end_timestamp))
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new(
+                "INSERT INTO reward_manifests(start_timestamp, end_timestamp, epoch, price, token, file_key, file_timestamp)",
+            )
+            .push_values(chunk, |mut b, (report, token, start_timestamp, end_timestamp)| {
+                b.push_bind(*start_timestamp)
+                    .push_bind(*end_timestamp)
                     .push_bind(report.epoch as i64)
                     .push_bind(report.price as i64)
-                    .push_bind(token);
+                    .push_bind(*token)
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
-        Ok(())
+        Ok(written as usize)
+    }
+}
+
+impl Record for RewardManifest {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime(self.end_timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let token = match &self.reward_data {
+            Some(RewardData::MobileRewardData(mobile)) => Some(mobile.token().as_str_name()),
+            Some(RewardData::IotRewardData(iot)) => Some(iot.token().as_str_name()),
+            None => None,
+        };
+
+        json!({
+            "written_files": self.written_files,
+            "start_timestamp": to_datetime(self.start_timestamp).ok(),
+            "end_timestamp": to_datetime(self.end_timestamp).ok(),
+            "epoch": self.epoch,
+            "price": self.price,
+            "token": token,
+        })
     }
 }
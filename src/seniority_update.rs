@@ -1,16 +1,20 @@
-use core::panic;
-
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{
     services::poc_mobile::{seniority_update, SeniorityUpdate},
     Message,
 };
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{to_datetime_ms, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime_ms, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeSeniorityUpdate;
@@ -21,18 +25,9 @@ impl ToPrefix for FileTypeSeniorityUpdate {
     }
 }
 
-#[async_trait::async_trait]
 impl Decode for FileTypeSeniorityUpdate {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(
-                |buf| async move { SeniorityUpdate::decode(buf).map_err(anyhow::Error::from) },
-            )
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, SeniorityUpdate::decode)
     }
 }
 
@@ -46,48 +41,132 @@ impl DbTable for FileTypeSeniorityUpdate {
                 radio_type text not null,
                 radio_key text not null,
                 new_seniority_timestamp timestamptz not null,
-                reason text not null
+                reason text not null,
+                file_key text
             )
         "#,
         )
         .execute(db)
+        .await?;
+
+        // a database restored from the verifier already has a seniority table, which
+        // CREATE OR REPLACE VIEW can't replace
+        let relkind: Option<String> = sqlx::query_scalar(
+            "SELECT relkind::text FROM pg_class WHERE oid = to_regclass('seniority')",
+        )
+        .fetch_optional(db)
+        .await?;
+        if relkind.is_some_and(|relkind| relkind != "v") {
+            anyhow::bail!(
+                "seniority already exists and is not a view, rename or drop it before importing seniority updates"
+            );
+        }
+
+        // the verifier's seniority table, as the reward analyzer's coverage query reads it. The
+        // file timestamp stands in for inserted_at, the update is written when it is inserted.
+        sqlx::query(
+            r#"
+            CREATE OR REPLACE VIEW seniority AS
+            SELECT
+                radio_key,
+                radio_type,
+                new_seniority_timestamp AS seniority_ts,
+                file_timestamp AS inserted_at,
+                reason
+            FROM seniority_updates
+        "#,
+        )
+        .execute(db)
         .await
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "seniority_updates",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["seniority_updates"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "seniority_updates_radio_key_idx",
+            table: "seniority_updates",
+            columns: "radio_key, file_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<SeniorityUpdate> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 4) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO seniority_updates(file_timestamp,radio_type, radio_key, new_seniority_timestamp, reason)")
-            .push_values(chunk, |mut b, report| {
-
-                let (key_type, key_value) = match report.key_type.clone() {
-                    Some(seniority_update::KeyType::HotspotKey(pubkey)) => ("wifi", PublicKeyBinary::from(pubkey).to_string()),
-                    Some(seniority_update::KeyType::CbsdId(cbsd_id)) => ("cbrs", cbsd_id),
-                    _ => panic!("invalid key type"),
-                };
-
-                b.push_bind(file_timestamp)
-                .push_bind(key_type)
-                .push_bind(key_value)
-                .push_bind(to_datetime_ms(report.new_seniority_timestamp_ms))
-                .push_bind(report.reason().as_str_name());
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 6) as usize;
+
+        let rows = errors.rows(self, |report| {
+            let (key_type, key_value) = match report.key_type.clone() {
+                Some(seniority_update::KeyType::HotspotKey(pubkey)) => {
+                    ("wifi", PublicKeyBinary::from(pubkey).to_string())
+                }
+                Some(seniority_update::KeyType::CbsdId(cbsd_id)) => ("cbrs", cbsd_id),
+                None => return Err(RecordError::MissingField("key_type")),
+            };
+            let new_seniority_timestamp = to_datetime_ms(report.new_seniority_timestamp_ms)?;
+            Ok((report, key_type, key_value, new_seniority_timestamp))
+        })?;
+
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO seniority_updates(file_timestamp,radio_type, radio_key, new_seniority_timestamp, reason, file_key)")
+            .push_values(chunk, |mut b, (report, key_type, key_value, new_seniority_timestamp)| {
+                b.push_bind(file_info.timestamp)
+                .push_bind(*key_type)
+                .push_bind(key_value.clone())
+                .push_bind(*new_seniority_timestamp)
+                .push_bind(report.reason().as_str_name())
+                .push_bind(&file_info.key);
 
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
 
-        Ok(())
+        Ok(written as usize)
+    }
+}
+
+impl Record for SeniorityUpdate {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime_ms(self.new_seniority_timestamp_ms).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let (radio_type, radio_key) = match &self.key_type {
+            Some(seniority_update::KeyType::HotspotKey(pubkey)) => {
+                ("wifi", PublicKeyBinary::from(pubkey.clone()).to_string())
+            }
+            Some(seniority_update::KeyType::CbsdId(cbsd_id)) => ("cbrs", cbsd_id.clone()),
+            None => ("unknown", String::new()),
+        };
+
+        json!({
+            "radio_type": radio_type,
+            "radio_key": radio_key,
+            "new_seniority_timestamp": to_datetime_ms(self.new_seniority_timestamp_ms).ok(),
+            "reason": self.reason().as_str_name(),
+        })
     }
 }
@@ -1,34 +1,38 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{
     services::poc_mobile::{
-        service_provider_boosted_rewards_banned_radio_req_v1::KeyType,
+        service_provider_boosted_rewards_banned_radio_req_v1::{
+            KeyType, SpBoostedRewardsBannedRadioReason,
+        },
+        ServiceProviderBoostedRewardsBannedRadioVerificationStatus,
         VerifiedServiceProviderBoostedRewardsBannedRadioIngestReportV1,
     },
     Message,
 };
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{to_datetime, to_datetime_ms, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, to_datetime_ms, CleanTarget, DbTable, Decode, Insertable, InsertableStream,
+    Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeServiceProviderBan;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeServiceProviderBan {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                VerifiedServiceProviderBoostedRewardsBannedRadioIngestReportV1::decode(buf)
-                    .map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(
+            stream,
+            keep_raw,
+            VerifiedServiceProviderBoostedRewardsBannedRadioIngestReportV1::decode,
+        )
     }
 }
 
@@ -51,54 +55,155 @@ impl DbTable for FileTypeServiceProviderBan {
                 until timestamptz not null,
                 received_timestamp timestamptz not null,
                 status text not null,
-                file_timestamp timestamptz not null
+                file_timestamp timestamptz not null,
+                file_key text
             )
         "#,
         )
         .execute(db)
+        .await?;
+
+        // service_provider_bans keeps every report, unbans included. This is the verifier's
+        // sp_boosted_rewards_bans: valid bans, invalidated by the first valid unban of the
+        // radio received after them.
+        let unbanned = SpBoostedRewardsBannedRadioReason::Unbanned.as_str_name();
+        let valid =
+            ServiceProviderBoostedRewardsBannedRadioVerificationStatus::SpBoostedRewardsBanValid
+                .as_str_name();
+        sqlx::query(&format!(
+            r#"
+            CREATE OR REPLACE VIEW sp_boosted_rewards_bans AS
+            SELECT
+                b.radio_type,
+                b.radio_key,
+                b.received_timestamp,
+                b.until,
+                b.ban_type,
+                b.reason,
+                (
+                    SELECT min(u.received_timestamp)
+                    FROM service_provider_bans u
+                    WHERE u.radio_type = b.radio_type
+                        AND u.radio_key = b.radio_key
+                        AND u.reason = '{unbanned}'
+                        AND u.status = '{valid}'
+                        AND u.received_timestamp > b.received_timestamp
+                ) AS invalidated_at
+            FROM service_provider_bans b
+            WHERE b.reason <> '{unbanned}'
+                AND b.status = '{valid}'
+        "#
+        ))
+        .execute(db)
         .await
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "service_provider_bans",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["service_provider_bans"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "service_provider_bans_radio_key_idx",
+            table: "service_provider_bans",
+            columns: "radio_key, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<VerifiedServiceProviderBoostedRewardsBannedRadioIngestReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 8) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO service_provider_bans(radio_key, radio_type, reason, ban_type, until, received_timestamp, status, file_timestamp)")
-            .push_values(chunk, |mut b, r| {
-                let ingest_report = r.clone().report.unwrap();
-                let report = ingest_report.clone().report.unwrap();
-
-                let (radio_key, radio_type) =
-                match report.key_type.clone() {
-                    Some(KeyType::HotspotKey(hk)) => (PublicKeyBinary::from(hk).to_string(), "wifi"),
-                    Some(KeyType::CbsdId(cbsd_id)) => (cbsd_id, "cbrs"),
-                    None => panic!("invalid radio"),
-                };
-
-                b.push_bind(radio_key)
-                    .push_bind(radio_type)
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 9) as usize;
+
+        let rows = errors.rows(self, |r| {
+            let ingest_report = r
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let report = ingest_report
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report.report"))?;
+
+            let (radio_key, radio_type) = match report.key_type.clone() {
+                Some(KeyType::HotspotKey(hk)) => (PublicKeyBinary::from(hk).to_string(), "wifi"),
+                Some(KeyType::CbsdId(cbsd_id)) => (cbsd_id, "cbrs"),
+                None => return Err(RecordError::MissingField("key_type")),
+            };
+            let until = to_datetime(report.until)?;
+            let received_timestamp = to_datetime_ms(ingest_report.received_timestamp)?;
+            Ok((r, report, radio_key, radio_type, until, received_timestamp))
+        })?;
+
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO service_provider_bans(radio_key, radio_type, reason, ban_type, until, received_timestamp, status, file_timestamp, file_key)")
+            .push_values(chunk, |mut b, (r, report, radio_key, radio_type, until, received_timestamp)| {
+                b.push_bind(radio_key.clone())
+                    .push_bind(*radio_type)
                     .push_bind(report.reason().as_str_name())
                     .push_bind(report.ban_type().as_str_name())
-                    .push_bind(to_datetime(report.until))
-                    .push_bind(to_datetime_ms(ingest_report.received_timestamp))
+                    .push_bind(*until)
+                    .push_bind(*received_timestamp)
                     .push_bind(r.status().as_str_name())
-                    .push_bind(file_timestamp);
+                    .push_bind(file_info.timestamp)
+                    .push_bind(&file_info.key);
 
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
 
-        Ok(())
+        Ok(written as usize)
+    }
+}
+
+impl Record for VerifiedServiceProviderBoostedRewardsBannedRadioIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.report
+            .as_ref()
+            .and_then(|r| to_datetime_ms(r.received_timestamp).ok())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let ingest = self.report.as_ref();
+        let report = ingest.and_then(|r| r.report.as_ref());
+
+        let (radio_key, radio_type) = match report.and_then(|r| r.key_type.as_ref()) {
+            Some(KeyType::HotspotKey(hk)) => {
+                (PublicKeyBinary::from(hk.clone()).to_string(), "wifi")
+            }
+            Some(KeyType::CbsdId(cbsd_id)) => (cbsd_id.clone(), "cbrs"),
+            None => (String::new(), "unknown"),
+        };
+
+        json!({
+            "status": self.status().as_str_name(),
+            "received_timestamp": ingest.and_then(|r| to_datetime_ms(r.received_timestamp).ok()),
+            "radio_key": radio_key,
+            "radio_type": radio_type,
+            "reason": report.map(|r| r.reason().as_str_name()),
+            "ban_type": report.map(|r| r.ban_type().as_str_name()),
+            "until": report.and_then(|r| to_datetime(r.until).ok()),
+        })
     }
 }
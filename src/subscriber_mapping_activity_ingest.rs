@@ -1,26 +1,27 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_proto::{services::poc_mobile::SubscriberMappingActivityIngestReportV1, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{determine_timestamp, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches, determine_timestamp,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Debug, Clone)]
 pub struct FileTypeSubscriberMappingActivityIngest;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeSubscriberMappingActivityIngest {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                SubscriberMappingActivityIngestReportV1::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(
+            stream,
+            keep_raw,
+            SubscriberMappingActivityIngestReportV1::decode,
+        )
     }
 }
 
@@ -40,7 +41,9 @@ impl DbTable for FileTypeSubscriberMappingActivityIngest {
                 discovery_reward_shares bigint not null,
                 verification_reward_shares bigint not null,
                 timestamp timestamptz not null,
-                received_timestamp timestamptz not null
+                received_timestamp timestamptz not null,
+                file_key text,
+                file_timestamp timestamptz
             )
         "#,
         )
@@ -49,37 +52,87 @@ impl DbTable for FileTypeSubscriberMappingActivityIngest {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "subscriber_mapping_activity_ingest",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["subscriber_mapping_activity_ingest"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "subscriber_mapping_activity_ingest_subscriber_id_idx",
+            table: "subscriber_mapping_activity_ingest",
+            columns: "subscriber_id, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<SubscriberMappingActivityIngestReportV1> {
     async fn insert(
         &self,
-        pool: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 5) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO subscriber_mapping_activity_ingest (subscriber_id, discovery_reward_shares, verification_reward_shares, timestamp, received_timestamp)")
-            .push_values(chunk, |mut b, ingest| {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 7) as usize;
 
-                let req = ingest.report.as_ref().unwrap().clone();
+        let rows = errors.rows(self, |ingest| {
+            let req = ingest
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            Ok((
+                req,
+                determine_timestamp(req.timestamp)?,
+                determine_timestamp(ingest.received_timestamp)?,
+            ))
+        })?;
 
-                b.push_bind(req.subscriber_id)
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO subscriber_mapping_activity_ingest (subscriber_id, discovery_reward_shares, verification_reward_shares, timestamp, received_timestamp, file_key, file_timestamp)")
+            .push_values(chunk, |mut b, (req, timestamp, received_timestamp)| {
+                b.push_bind(req.subscriber_id.clone())
                     .push_bind(req.discovery_reward_shares as i64)
                     .push_bind(req.verification_reward_shares as i64)
-                    .push_bind(determine_timestamp(req.timestamp))
-                    .push_bind(determine_timestamp(ingest.received_timestamp));
-
-                
+                    .push_bind(*timestamp)
+                    .push_bind(*received_timestamp)
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
             })
             .build()
-            .execute(pool)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
 
-        Ok(())
+        Ok(written as usize)
     }
 }
 
+impl Record for SubscriberMappingActivityIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        determine_timestamp(self.received_timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let req = self.report.as_ref();
+
+        json!({
+            "received_timestamp": determine_timestamp(self.received_timestamp).ok(),
+            "subscriber_id": req.map(|r| bs58::encode(&r.subscriber_id).into_string()),
+            "discovery_reward_shares": req.map(|r| r.discovery_reward_shares),
+            "verification_reward_shares": req.map(|r| r.verification_reward_shares),
+            "timestamp": req.and_then(|r| determine_timestamp(r.timestamp).ok()),
+        })
+    }
+}
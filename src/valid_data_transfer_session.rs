@@ -1,27 +1,24 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{services::packet_verifier::ValidDataTransferSession, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{to_datetime_ms, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    record_errors::RecordErrors,
+    to_datetime_ms, CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeValidDataTransferSession;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeValidDataTransferSession {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                ValidDataTransferSession::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, ValidDataTransferSession::decode)
     }
 }
 
@@ -45,7 +42,9 @@ impl DbTable for FileTypeValidDataTransferSession {
                     first_timestamp timestamptz not null,
                     last_timestamp timestamptz not null,
                     rewardable_bytes bigint not null,
-                    received_timestamp timestamptz not null
+                    received_timestamp timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
                 )
             "#,
         )
@@ -54,34 +53,87 @@ impl DbTable for FileTypeValidDataTransferSession {
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "valid_data_transfer_sessions",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["valid_data_transfer_sessions"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "valid_data_transfer_sessions_pub_key_idx",
+            table: "valid_data_transfer_sessions",
+            columns: "pub_key, received_timestamp",
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<ValidDataTransferSession> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 8) as usize;
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 11) as usize;
+
+        let rows = errors.rows(self, |report| {
+            Ok((
+                report,
+                to_datetime_ms(report.first_timestamp)?,
+                to_datetime_ms(report.last_timestamp)?,
+            ))
+        })?;
 
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO valid_data_transfer_sessions(pub_key, payer, upload_bytes, download_bytes, num_dcs, first_timestamp, last_timestamp, rewardable_bytes, received_timestamp)")
-            .push_values(chunk, |mut b, report| {
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO valid_data_transfer_sessions(pub_key, payer, upload_bytes, download_bytes, num_dcs, first_timestamp, last_timestamp, rewardable_bytes, received_timestamp, file_key, file_timestamp)")
+            .push_values(chunk, |mut b, (report, first_timestamp, last_timestamp)| {
                 b.push_bind(PublicKeyBinary::from(report.pub_key.clone()).to_string())
                     .push_bind(PublicKeyBinary::from(report.payer.clone()).to_string())
                     .push_bind(report.upload_bytes as i64)
                     .push_bind(report.download_bytes as i64)
                     .push_bind(report.num_dcs as i64)
-                    .push_bind(to_datetime_ms(report.first_timestamp))
-                    .push_bind(to_datetime_ms(report.last_timestamp))
+                    .push_bind(*first_timestamp)
+                    .push_bind(*last_timestamp)
                     .push_bind(report.rewardable_bytes as i64)
-                    .push_bind(file_timestamp);
+                    .push_bind(file_info.timestamp)
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
-        Ok(())
+        Ok(written as usize)
+    }
+}
+
+impl Record for ValidDataTransferSession {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime_ms(self.last_timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "pub_key": PublicKeyBinary::from(self.pub_key.clone()).to_string(),
+            "payer": PublicKeyBinary::from(self.payer.clone()).to_string(),
+            "upload_bytes": self.upload_bytes,
+            "download_bytes": self.download_bytes,
+            "num_dcs": self.num_dcs,
+            "first_timestamp": to_datetime_ms(self.first_timestamp).ok(),
+            "last_timestamp": to_datetime_ms(self.last_timestamp).ok(),
+            "rewardable_bytes": self.rewardable_bytes,
+        })
     }
 }
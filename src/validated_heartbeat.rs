@@ -1,26 +1,27 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
-use helium_crypto::PublicKey;
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::{PublicKey, PublicKeyBinary};
 use helium_proto::{services::poc_mobile::Heartbeat, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::{determine_timestamp, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches, determine_timestamp,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    partitions::Partitioned,
+    pg_copy,
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Debug, Clone)]
 pub struct FileTypeValidatedHeartbeat {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeValidatedHeartbeat {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move { Heartbeat::decode(buf).map_err(anyhow::Error::from) })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, Heartbeat::decode)
     }
 }
 
@@ -30,69 +31,156 @@ impl ToPrefix for FileTypeValidatedHeartbeat {
     }
 }
 
+const COLUMNS: &str = r#"
+    hotspot_key text not null,
+    cbsd_id text,
+    reward_multiplier numeric not null,
+    cell_type text not null,
+    validity text not null,
+    location_validation_timestamp timestamptz,
+    distance_to_asserted bigint,
+    location_trust_score_multiplier int4,
+    timestamp timestamptz,
+    lat numeric,
+    lon numeric,
+    coverage_object text,
+    file_key text,
+    file_timestamp timestamptz
+"#;
+
 #[async_trait::async_trait]
 impl DbTable for FileTypeValidatedHeartbeat {
     async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
-        sqlx::query(
-            r#"
-                CREATE TABLE IF NOT EXISTS mobile_validated_heartbeats (
-                    hotspot_key text not null,
-                    cbsd_id text,
-                    reward_multiplier numeric not null,
-                    cell_type text not null,
-                    validity text not null,
-                    location_validation_timestamp timestamptz,
-                    distance_to_asserted bigint,
-                    location_trust_score_multiplier int4,
-                    timestamp timestamptz,
-                    lat numeric,
-                    lon numeric,
-                    coverage_object text
-                )
-            "#,
-        )
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS mobile_validated_heartbeats ({COLUMNS})"
+        ))
         .execute(db)
         .await
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "mobile_validated_heartbeats",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["mobile_validated_heartbeats"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![
+            Index {
+                name: "mobile_validated_heartbeats_hotspot_key_idx",
+                table: "mobile_validated_heartbeats",
+                columns: "hotspot_key, timestamp",
+            },
+            Index {
+                name: "mobile_validated_heartbeats_coverage_object_idx",
+                table: "mobile_validated_heartbeats",
+                columns: "coverage_object",
+            },
+        ]
+    }
+
+    fn partitioned(&self) -> Vec<Partitioned> {
+        vec![Partitioned {
+            table: "mobile_validated_heartbeats",
+            column: "timestamp",
+            columns: COLUMNS,
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<Heartbeat> {
     async fn insert(
         &self,
-        pool: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 12) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            let mut qb = QueryBuilder::new("INSERT INTO mobile_validated_heartbeats(hotspot_key, cbsd_id, reward_multiplier, cell_type, validity, location_validation_timestamp, distance_to_asserted, timestamp, location_trust_score_multiplier, lat, lon, coverage_object)");
-
-            qb.push_values(chunk, |mut b, hb| {
-                b.push_bind(PublicKey::try_from(hb.pub_key.clone()).unwrap().to_string())
-                    .push_bind(&hb.cbsd_id)
-                    .push_bind(0)
-                    .push_bind(hb.cell_type().as_str_name())
-                    .push_bind(hb.validity().as_str_name())
-                    .push_bind(determine_timestamp(hb.location_validation_timestamp))
-                    .push_bind(hb.distance_to_asserted as i64)
-                    .push_bind(determine_timestamp(hb.timestamp))
-                    .push_bind(hb.location_trust_score_multiplier as i64)
-                    .push_bind(hb.lat)
-                    .push_bind(hb.lon)
-                    .push_bind(
-                        Uuid::from_slice(hb.coverage_object.as_slice())
-                            .map(|u| u.to_string())
-                            .unwrap_or("invalid".to_string()),
-                    );
-            })
-            .build()
-            .execute(pool)
-            .await?;
-        }
-
-        Ok(())
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let rows = errors.rows(self, |hb| {
+            let hotspot_key =
+                PublicKey::try_from(hb.pub_key.clone()).map_err(RecordError::invalid("pub_key"))?;
+            // some invalid heartbeats never got a coverage object
+            let coverage_object = if hb.coverage_object.is_empty() {
+                None
+            } else {
+                Some(
+                    Uuid::from_slice(&hb.coverage_object)
+                        .map_err(RecordError::invalid("coverage_object"))?,
+                )
+            };
+            Ok((
+                hb,
+                hotspot_key.to_string(),
+                determine_timestamp(hb.location_validation_timestamp)?,
+                determine_timestamp(hb.timestamp)?,
+                coverage_object,
+            ))
+        })?;
+
+        let written = pg_copy::copy_in(
+            transaction,
+            "mobile_validated_heartbeats(hotspot_key, cbsd_id, reward_multiplier, cell_type, validity, location_validation_timestamp, distance_to_asserted, timestamp, location_trust_score_multiplier, lat, lon, coverage_object, file_key, file_timestamp)",
+            rows,
+            |mut row,
+             (hb, hotspot_key, location_validation_timestamp, timestamp, coverage_object)| {
+                row.push(hotspot_key)
+                    .push(&hb.cbsd_id)
+                    .push(0)
+                    .push(hb.cell_type().as_str_name())
+                    .push(hb.validity().as_str_name())
+                    .push(location_validation_timestamp)
+                    .push(hb.distance_to_asserted as i64)
+                    .push(timestamp)
+                    .push(hb.location_trust_score_multiplier as i64)
+                    .push(hb.lat)
+                    .push(hb.lon)
+                    .push(coverage_object)
+                    .push(&file_info.key)
+                    .push(file_info.timestamp);
+                Ok(())
+            },
+        )
+        .await?;
+
+        Ok(written as usize)
+    }
+
+    fn partition_keys(&self, _file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+        // records with a bad timestamp are rejected on insert and need no partition
+        self.iter()
+            .filter_map(|hb| determine_timestamp(hb.timestamp).ok())
+            .map(|timestamp| ("mobile_validated_heartbeats", timestamp))
+            .collect()
+    }
+}
+
+impl Record for Heartbeat {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        determine_timestamp(self.timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "hotspot_key": PublicKeyBinary::from(self.pub_key.clone()).to_string(),
+            "cbsd_id": self.cbsd_id,
+            "cell_type": self.cell_type().as_str_name(),
+            "validity": self.validity().as_str_name(),
+            "location_validation_timestamp": determine_timestamp(self.location_validation_timestamp).ok(),
+            "distance_to_asserted": self.distance_to_asserted,
+            "timestamp": determine_timestamp(self.timestamp).ok(),
+            "location_trust_score_multiplier": self.location_trust_score_multiplier,
+            "lat": self.lat,
+            "lon": self.lon,
+            "coverage_object": Uuid::from_slice(self.coverage_object.as_slice()).ok(),
+        })
     }
 }
@@ -0,0 +1,192 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use bytes::BytesMut;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::PublicKey;
+use helium_proto::{
+    services::poc_mobile::{Heartbeat, HeartbeatValidity},
+    DecodeError, Message,
+};
+use rust_decimal::Decimal;
+use sqlx::{Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    decode_batches, determine_timestamp,
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
+
+// The verifier writes wifi and cbrs heartbeats to the same validated_heartbeat files, this
+// file type keeps only the valid wifi ones, shaped like the verifier's wifi_heartbeats table.
+#[derive(Clone, Debug)]
+pub struct FileTypeValidatedWifiHeartbeat;
+
+#[derive(Clone, Debug)]
+pub struct ValidatedWifiHeartbeat(Heartbeat);
+
+fn decode(buf: BytesMut) -> Result<ValidatedWifiHeartbeat, DecodeError> {
+    Heartbeat::decode(buf).map(ValidatedWifiHeartbeat)
+}
+
+impl Decode for FileTypeValidatedWifiHeartbeat {
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, decode)
+    }
+}
+
+impl ToPrefix for FileTypeValidatedWifiHeartbeat {
+    fn to_prefix(&self) -> String {
+        FileType::ValidatedHeartbeat.to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl DbTable for FileTypeValidatedWifiHeartbeat {
+    async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+                CREATE TABLE IF NOT EXISTS wifi_heartbeats (
+                    hotspot_key text not null,
+                    cell_type text not null,
+                    truncated_timestamp timestamptz not null,
+                    timestamp timestamptz not null,
+                    location_validation_timestamp timestamptz,
+                    distance_to_asserted bigint not null,
+                    location_trust_score_multiplier numeric not null,
+                    coverage_object text not null,
+                    file_key text,
+                    file_timestamp timestamptz
+                )
+            "#,
+        )
+        .execute(db)
+        .await?;
+
+        // built with the table rather than in `indexes` since inserts upsert on it
+        sqlx::query(
+            r#"
+                CREATE UNIQUE INDEX IF NOT EXISTS wifi_heartbeats_hotspot_key_truncated_timestamp_idx
+                ON wifi_heartbeats (hotspot_key, truncated_timestamp)
+            "#,
+        )
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(anyhow::Error::from)
+    }
+
+    // a row keeps the file key and timestamp of the file that first wrote its hotspot and
+    // hour, even when a later file replaces the heartbeat. Cleaning that first file deletes
+    // the row, cleaning the later one leaves it.
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "wifi_heartbeats",
+        }]
+    }
+}
+
+#[async_trait::async_trait]
+impl Insertable for Vec<ValidatedWifiHeartbeat> {
+    async fn insert(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 10) as usize;
+
+        let rows = errors.rows(self, |ValidatedWifiHeartbeat(hb)| {
+            // cbrs heartbeats and invalid wifi heartbeats never make it into wifi_heartbeats
+            if !hb.cbsd_id.is_empty() || hb.validity() != HeartbeatValidity::Valid {
+                return Ok(None);
+            }
+
+            let hotspot_key =
+                PublicKey::try_from(hb.pub_key.clone()).map_err(RecordError::invalid("pub_key"))?;
+            let coverage_object = Uuid::from_slice(&hb.coverage_object)
+                .map_err(RecordError::invalid("coverage_object"))?;
+            let timestamp = determine_timestamp(hb.timestamp)?;
+            let truncated_timestamp = timestamp
+                .duration_trunc(Duration::hours(1))
+                .map_err(RecordError::invalid("timestamp"))?;
+            let location_validation_timestamp = if hb.location_validation_timestamp == 0 {
+                None
+            } else {
+                Some(determine_timestamp(hb.location_validation_timestamp)?)
+            };
+            Ok(Some((
+                (hotspot_key.to_string(), truncated_timestamp),
+                (
+                    hb,
+                    coverage_object,
+                    timestamp,
+                    location_validation_timestamp,
+                ),
+            )))
+        })?;
+
+        // the verifier keeps one heartbeat per hotspot and hour, the latest one
+        let mut latest: HashMap<(String, DateTime<Utc>), _> = HashMap::new();
+        for (key, row) in rows.into_iter().flatten() {
+            match latest.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(row);
+                }
+                Entry::Occupied(mut entry) => {
+                    if row.2 > entry.get().2 {
+                        entry.insert(row);
+                    }
+                }
+            }
+        }
+        let rows: Vec<_> = latest.into_iter().collect();
+
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO wifi_heartbeats(hotspot_key, cell_type, truncated_timestamp, timestamp, location_validation_timestamp, distance_to_asserted, location_trust_score_multiplier, coverage_object, file_key, file_timestamp)")
+            .push_values(chunk, |mut b, ((hotspot_key, truncated_timestamp), (hb, coverage_object, timestamp, location_validation_timestamp))| {
+                b.push_bind(hotspot_key.clone())
+                    .push_bind(hb.cell_type().as_str_name())
+                    .push_bind(*truncated_timestamp)
+                    .push_bind(*timestamp)
+                    .push_bind(*location_validation_timestamp)
+                    .push_bind(hb.distance_to_asserted as i64)
+                    // the heartbeat carries the multiplier scaled by 1000
+                    .push_bind(Decimal::new(hb.location_trust_score_multiplier as i64, 3))
+                    .push_bind(coverage_object.to_string())
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
+            })
+            .push(
+                r#"
+                ON CONFLICT (hotspot_key, truncated_timestamp) DO UPDATE SET
+                    cell_type = EXCLUDED.cell_type,
+                    timestamp = EXCLUDED.timestamp,
+                    location_validation_timestamp = EXCLUDED.location_validation_timestamp,
+                    distance_to_asserted = EXCLUDED.distance_to_asserted,
+                    location_trust_score_multiplier = EXCLUDED.location_trust_score_multiplier,
+                    coverage_object = EXCLUDED.coverage_object
+                WHERE wifi_heartbeats.timestamp < EXCLUDED.timestamp
+                "#,
+            )
+            .build()
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        }
+
+        Ok(written as usize)
+    }
+}
+
+impl Record for ValidatedWifiHeartbeat {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.0.timestamp()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        self.0.to_json()
+    }
+}
@@ -1,27 +1,27 @@
 use chrono::{DateTime, Utc};
-use file_store::{BytesMutStream, FileType};
-use futures::TryStreamExt;
+use file_store::{BytesMutStream, FileInfo, FileType};
 use helium_crypto::PublicKeyBinary;
 use helium_proto::{services::poc_mobile::VerifiedDataTransferIngestReportV1, Message};
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
 
-use crate::{to_datetime, to_datetime_ms, DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    partitions::Partitioned,
+    pg_copy,
+    record_errors::{RecordError, RecordErrors},
+    to_datetime, to_datetime_ms, CleanTarget, DbTable, Decode, Insertable, InsertableStream,
+    Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeVerifiedDataTransferIngest;
 
-#[async_trait::async_trait]
 impl Decode for FileTypeVerifiedDataTransferIngest {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                VerifiedDataTransferIngestReportV1::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, VerifiedDataTransferIngestReportV1::decode)
     }
 }
 
@@ -31,67 +31,161 @@ impl ToPrefix for FileTypeVerifiedDataTransferIngest {
     }
 }
 
+const COLUMNS: &str = r#"
+    status text not null,
+    verified_timestamp timestamptz not null,
+    pub_key text not null,
+    received_timestamp timestamptz not null,
+    timestamp timestamptz not null,
+    payer text not null,
+    upload_bytes bigint not null,
+    download_bytes bigint not null,
+    rewardable_bytes bigint not null,
+    reward_cancelled bool not null,
+    event_id text not null,
+    rat text,
+    file_key text,
+    file_timestamp timestamptz
+"#;
+
 #[async_trait::async_trait]
 impl DbTable for FileTypeVerifiedDataTransferIngest {
     async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
-        sqlx::query(
-            r#"
-                CREATE TABLE IF NOT EXISTS verified_data_transfer_ingest(
-                    status text not null,
-                    verified_timestamp timestamptz not null,
-                    pub_key text not null,
-                    received_timestamp timestamptz not null,
-                    timestamp timestamptz not null,
-                    payer text not null,
-                    upload_bytes bigint not null,
-                    download_bytes bigint not null,
-                    rewardable_bytes bigint not null,
-                    reward_cancelled bool not null,
-                    event_id text not null,
-                    rat text
-                )
-            "#,
-        )
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS verified_data_transfer_ingest ({COLUMNS})"
+        ))
         .execute(db)
         .await
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "verified_data_transfer_ingest",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![
+            Migration {
+                version: 1,
+                sql: "ALTER TABLE verified_data_transfer_ingest ADD COLUMN IF NOT EXISTS rat text"
+                    .to_string(),
+            },
+            Migration {
+                version: 2,
+                sql: provenance_migration(&["verified_data_transfer_ingest"]),
+            },
+        ]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "verified_data_transfer_ingest_pub_key_idx",
+            table: "verified_data_transfer_ingest",
+            columns: "pub_key, received_timestamp",
+        }]
+    }
+
+    fn partitioned(&self) -> Vec<Partitioned> {
+        vec![Partitioned {
+            table: "verified_data_transfer_ingest",
+            column: "verified_timestamp",
+            columns: COLUMNS,
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<VerifiedDataTransferIngestReportV1> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 12) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO verified_data_transfer_ingest(status, verified_timestamp, pub_key, received_timestamp, timestamp, payer, upload_bytes, download_bytes, rewardable_bytes, reward_cancelled, event_id, rat)")
-            .push_values(chunk, |mut b, report| {
-
-                let req = report.clone().report.unwrap().report.unwrap();
-                let usage = req.data_transfer_usage.unwrap();
-
-                b.push_bind(report.status().as_str_name())
-                    .push_bind(to_datetime_ms(report.timestamp))
-                    .push_bind(PublicKeyBinary::from(usage.pub_key.clone()).to_string())
-                    .push_bind(to_datetime_ms(report.clone().report.unwrap().received_timestamp))
-                    .push_bind(to_datetime(usage.timestamp))
-                    .push_bind(PublicKeyBinary::from(usage.payer.clone()).to_string())
-                    .push_bind(usage.upload_bytes as i64)
-                    .push_bind(usage.download_bytes as i64)
-                    .push_bind(req.rewardable_bytes as i64)
-                    .push_bind(req.reward_cancelled)
-                    .push_bind(usage.event_id.clone())
-                    .push_bind(usage.radio_access_technology().as_str_name());
-            })
-            .build()
-            .execute(db)
-            .await?;
-        }
-        Ok(())
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let rows = errors.rows(self, |report| {
+            let ingest = report
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let req = ingest
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report.report"))?;
+            let usage = req
+                .data_transfer_usage
+                .as_ref()
+                .ok_or(RecordError::MissingField("data_transfer_usage"))?;
+            Ok((
+                report,
+                req,
+                usage,
+                to_datetime_ms(report.timestamp)?,
+                to_datetime_ms(ingest.received_timestamp)?,
+                to_datetime(usage.timestamp)?,
+            ))
+        })?;
+
+        let written = pg_copy::copy_in(
+            transaction,
+            "verified_data_transfer_ingest(status, verified_timestamp, pub_key, received_timestamp, timestamp, payer, upload_bytes, download_bytes, rewardable_bytes, reward_cancelled, event_id, rat, file_key, file_timestamp)",
+            rows,
+            |mut row, (report, req, usage, verified_timestamp, received_timestamp, timestamp)| {
+                row.push(report.status().as_str_name())
+                    .push(verified_timestamp)
+                    .push(PublicKeyBinary::from(usage.pub_key.clone()).to_string())
+                    .push(received_timestamp)
+                    .push(timestamp)
+                    .push(PublicKeyBinary::from(usage.payer.clone()).to_string())
+                    .push(usage.upload_bytes as i64)
+                    .push(usage.download_bytes as i64)
+                    .push(req.rewardable_bytes as i64)
+                    .push(req.reward_cancelled)
+                    .push(&usage.event_id)
+                    .push(usage.radio_access_technology().as_str_name())
+                    .push(&file_info.key)
+                    .push(file_info.timestamp);
+                Ok(())
+            },
+        )
+        .await?;
+
+        Ok(written as usize)
+    }
+
+    fn partition_keys(&self, _file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+        self.iter()
+            .filter_map(|report| to_datetime_ms(report.timestamp).ok())
+            .map(|timestamp| ("verified_data_transfer_ingest", timestamp))
+            .collect()
+    }
+}
+
+impl Record for VerifiedDataTransferIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        to_datetime_ms(self.timestamp).ok()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let ingest = self.report.as_ref();
+        let req = ingest.and_then(|r| r.report.as_ref());
+        let usage = req.and_then(|r| r.data_transfer_usage.as_ref());
+
+        json!({
+            "status": self.status().as_str_name(),
+            "verified_timestamp": to_datetime_ms(self.timestamp).ok(),
+            "pub_key": usage.map(|u| PublicKeyBinary::from(u.pub_key.clone()).to_string()),
+            "received_timestamp": ingest.and_then(|r| to_datetime_ms(r.received_timestamp).ok()),
+            "timestamp": usage.and_then(|u| to_datetime(u.timestamp).ok()),
+            "payer": usage.map(|u| PublicKeyBinary::from(u.payer.clone()).to_string()),
+            "upload_bytes": usage.map(|u| u.upload_bytes),
+            "download_bytes": usage.map(|u| u.download_bytes),
+            "rewardable_bytes": req.map(|r| r.rewardable_bytes),
+            "reward_cancelled": req.map(|r| r.reward_cancelled),
+            "event_id": usage.map(|u| &u.event_id),
+            "rat": usage.map(|u| u.radio_access_technology().as_str_name()),
+        })
     }
 }
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use helium_proto::{
+    services::poc_mobile::{SpeedtestVerificationResult, VerifiedSpeedtest},
+    Message,
+};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    decode_batches, determine_timestamp,
+    indexes::Index,
+    pg_copy,
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
+
+// Valid speedtests only, shaped like the verifier's speedtests table the reward analyzer reads.
+#[derive(Clone, Debug)]
+pub struct FileTypeVerifiedSpeedtest;
+
+impl Decode for FileTypeVerifiedSpeedtest {
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, VerifiedSpeedtest::decode)
+    }
+}
+
+impl ToPrefix for FileTypeVerifiedSpeedtest {
+    fn to_prefix(&self) -> String {
+        FileType::VerifiedSpeedtest.to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl DbTable for FileTypeVerifiedSpeedtest {
+    async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+        // databases created before the reward share speedtests moved to radio_reward_speedtests
+        // still have them in speedtests
+        let reward_speedtests: bool = sqlx::query_scalar(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM information_schema.columns
+                    WHERE table_schema = current_schema()
+                        AND table_name = 'speedtests'
+                        AND column_name = 'id'
+                )
+            "#,
+        )
+        .fetch_one(db)
+        .await?;
+        if reward_speedtests {
+            anyhow::bail!(
+                "speedtests holds reward share speedtests, run `migrate --file-type mobile-reward-share` first"
+            );
+        }
+
+        sqlx::query(
+            r#"
+                CREATE TABLE IF NOT EXISTS speedtests (
+                    pubkey text not null,
+                    serial_num text not null,
+                    upload_speed bigint not null,
+                    download_speed bigint not null,
+                    latency integer not null,
+                    timestamp timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
+                )
+            "#,
+        )
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(anyhow::Error::from)
+    }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "speedtests",
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "speedtests_pubkey_idx",
+            table: "speedtests",
+            columns: "pubkey, timestamp",
+        }]
+    }
+}
+
+#[async_trait::async_trait]
+impl Insertable for Vec<VerifiedSpeedtest> {
+    async fn insert(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let rows = errors.rows(self, |speedtest| {
+            if speedtest.result() != SpeedtestVerificationResult::SpeedtestValid {
+                return Ok(None);
+            }
+
+            let report = speedtest
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let pubkey = PublicKey::try_from(report.pub_key.clone())
+                .map_err(RecordError::invalid("pub_key"))?;
+            let timestamp = determine_timestamp(report.timestamp)?;
+            Ok(Some((report, pubkey.to_string(), timestamp)))
+        })?;
+
+        let written = pg_copy::copy_in(
+            transaction,
+            "speedtests(pubkey, serial_num, upload_speed, download_speed, latency, timestamp, file_key, file_timestamp)",
+            rows.into_iter().flatten(),
+            |mut row, (report, pubkey, timestamp)| {
+                row.push(pubkey)
+                    .push(&report.serial)
+                    .push(report.upload_speed as i64)
+                    .push(report.download_speed as i64)
+                    .push(report.latency as i32)
+                    .push(timestamp)
+                    .push(&file_info.key)
+                    .push(file_info.timestamp);
+                Ok(())
+            },
+        )
+        .await?;
+
+        Ok(written as usize)
+    }
+}
+
+impl Record for VerifiedSpeedtest {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.report
+            .as_ref()
+            .and_then(|report| determine_timestamp(report.timestamp).ok())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let report = self.report.clone().unwrap_or_default();
+        json!({
+            "pubkey": PublicKeyBinary::from(report.pub_key).to_string(),
+            "serial": report.serial,
+            "upload_speed": report.upload_speed,
+            "download_speed": report.download_speed,
+            "latency": report.latency,
+            "timestamp": determine_timestamp(report.timestamp).ok(),
+            "result": self.result().as_str_name(),
+        })
+    }
+}
@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use file_store::{BytesMutStream, FileInfo, FileType};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use helium_proto::{
+    services::poc_mobile::{
+        VerifiedUniqueConnectionsIngestReportStatus, VerifiedUniqueConnectionsIngestReportV1,
+    },
+    Message,
+};
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+
+use crate::{
+    decode_batches, determine_timestamp,
+    indexes::Index,
+    pg_copy,
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
+
+// Valid reports only, shaped like the verifier's unique_connections table the reward analyzer
+// checks HIP-134 qualification against.
+#[derive(Clone, Debug)]
+pub struct FileTypeVerifiedUniqueConnections;
+
+impl Decode for FileTypeVerifiedUniqueConnections {
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(
+            stream,
+            keep_raw,
+            VerifiedUniqueConnectionsIngestReportV1::decode,
+        )
+    }
+}
+
+impl ToPrefix for FileTypeVerifiedUniqueConnections {
+    fn to_prefix(&self) -> String {
+        FileType::VerifiedUniqueConnectionsReport.to_string()
+    }
+}
+
+#[async_trait::async_trait]
+impl DbTable for FileTypeVerifiedUniqueConnections {
+    async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+                CREATE TABLE IF NOT EXISTS unique_connections (
+                    hotspot_pubkey text not null,
+                    start_timestamp timestamptz not null,
+                    end_timestamp timestamptz not null,
+                    unique_connections bigint not null,
+                    received_timestamp timestamptz not null,
+                    file_key text,
+                    file_timestamp timestamptz
+                )
+            "#,
+        )
+        .execute(db)
+        .await
+        .map(|_| ())
+        .map_err(anyhow::Error::from)
+    }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "unique_connections",
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![Index {
+            name: "unique_connections_hotspot_pubkey_idx",
+            table: "unique_connections",
+            columns: "hotspot_pubkey, received_timestamp",
+        }]
+    }
+}
+
+#[async_trait::async_trait]
+impl Insertable for Vec<VerifiedUniqueConnectionsIngestReportV1> {
+    async fn insert(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        let rows = errors.rows(self, |verified| {
+            if verified.status() != VerifiedUniqueConnectionsIngestReportStatus::Valid {
+                return Ok(None);
+            }
+
+            let ingest = verified
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report"))?;
+            let report = ingest
+                .report
+                .as_ref()
+                .ok_or(RecordError::MissingField("report.report"))?;
+            let pubkey = PublicKey::try_from(report.pubkey.clone())
+                .map_err(RecordError::invalid("pubkey"))?;
+            Ok(Some((
+                report,
+                pubkey.to_string(),
+                determine_timestamp(report.start_timestamp)?,
+                determine_timestamp(report.end_timestamp)?,
+                determine_timestamp(ingest.received_timestamp)?,
+            )))
+        })?;
+
+        let written = pg_copy::copy_in(
+            transaction,
+            "unique_connections(hotspot_pubkey, start_timestamp, end_timestamp, unique_connections, received_timestamp, file_key, file_timestamp)",
+            rows.into_iter().flatten(),
+            |mut row, (report, pubkey, start_timestamp, end_timestamp, received_timestamp)| {
+                row.push(pubkey)
+                    .push(start_timestamp)
+                    .push(end_timestamp)
+                    .push(report.unique_connections as i64)
+                    .push(received_timestamp)
+                    .push(&file_info.key)
+                    .push(file_info.timestamp);
+                Ok(())
+            },
+        )
+        .await?;
+
+        Ok(written as usize)
+    }
+}
+
+impl Record for VerifiedUniqueConnectionsIngestReportV1 {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.report
+            .as_ref()
+            .and_then(|ingest| determine_timestamp(ingest.received_timestamp).ok())
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let ingest = self.report.clone().unwrap_or_default();
+        let report = ingest.report.unwrap_or_default();
+        json!({
+            "hotspot_pubkey": PublicKeyBinary::from(report.pubkey).to_string(),
+            "start_timestamp": determine_timestamp(report.start_timestamp).ok(),
+            "end_timestamp": determine_timestamp(report.end_timestamp).ok(),
+            "unique_connections": report.unique_connections,
+            "received_timestamp": determine_timestamp(ingest.received_timestamp).ok(),
+            "status": self.status().as_str_name(),
+        })
+    }
+}
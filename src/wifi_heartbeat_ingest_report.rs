@@ -1,28 +1,27 @@
 use chrono::{DateTime, Utc};
 use file_store::{
-    traits::MsgDecode, wifi_heartbeat::WifiHeartbeatIngestReport, BytesMutStream, FileType,
+    traits::MsgDecode, wifi_heartbeat::WifiHeartbeatIngestReport, BytesMutStream, FileInfo,
+    FileType,
 };
 
-use futures::TryStreamExt;
-use sqlx::{Pool, Postgres, QueryBuilder};
+use serde_json::json;
+use sqlx::{Postgres, QueryBuilder, Transaction};
 
-use crate::{DbTable, Decode, Insertable, ToPrefix};
+use crate::{
+    decode_batches,
+    indexes::Index,
+    migrations::{provenance_migration, Migration},
+    partitions::Partitioned,
+    record_errors::{RecordError, RecordErrors},
+    CleanTarget, DbTable, Decode, Insertable, InsertableStream, Record, ToPrefix,
+};
 
 #[derive(Clone, Debug)]
 pub struct FileTypeWifiHeartbeatIngestReport {}
 
-#[async_trait::async_trait]
 impl Decode for FileTypeWifiHeartbeatIngestReport {
-    async fn decode(&self, stream: BytesMutStream) -> anyhow::Result<Box<dyn Insertable>> {
-        let reports = stream
-            .map_err(anyhow::Error::from)
-            .and_then(|buf| async move {
-                WifiHeartbeatIngestReport::decode(buf).map_err(anyhow::Error::from)
-            })
-            .try_collect::<Vec<_>>()
-            .await?;
-
-        Ok(Box::new(reports))
+    fn decode(&self, stream: BytesMutStream, keep_raw: bool) -> InsertableStream {
+        decode_batches(stream, keep_raw, WifiHeartbeatIngestReport::decode)
     }
 }
 
@@ -32,45 +31,88 @@ impl ToPrefix for FileTypeWifiHeartbeatIngestReport {
     }
 }
 
+const COLUMNS: &str = r#"
+    received_timestamp timestamptz not null,
+    hotspot_key text not null,
+    timestamp timestamptz not null,
+    lat numeric not null,
+    lon numeric not null,
+    location_validation_timestamp timestamptz,
+    operation_mode boolean not null,
+    coverage_object text,
+    file_key text,
+    file_timestamp timestamptz
+"#;
+
 #[async_trait::async_trait]
 impl DbTable for FileTypeWifiHeartbeatIngestReport {
     async fn create_table(&self, db: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
-        sqlx::query(
-            r#"
-                CREATE TABLE IF NOT EXISTS mobile_wifi_ingest_reports(
-                    received_timestamp timestamptz not null,
-                    hotspot_key text not null,
-                    timestamp timestamptz not null,
-                    lat numeric not null,
-                    lon numeric not null,
-                    location_validation_timestamp timestamptz,
-                    operation_mode boolean not null,
-                    coverage_object text
-                )
-            "#,
-        )
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS mobile_wifi_ingest_reports ({COLUMNS})"
+        ))
         .execute(db)
         .await
         .map(|_| ())
         .map_err(anyhow::Error::from)
     }
+
+    fn clean_targets(&self) -> Vec<CleanTarget> {
+        vec![CleanTarget::Table {
+            table: "mobile_wifi_ingest_reports",
+        }]
+    }
+
+    fn migrations(&self) -> Vec<Migration> {
+        vec![Migration {
+            version: 1,
+            sql: provenance_migration(&["mobile_wifi_ingest_reports"]),
+        }]
+    }
+
+    fn indexes(&self) -> Vec<Index> {
+        vec![
+            Index {
+                name: "mobile_wifi_ingest_reports_hotspot_key_idx",
+                table: "mobile_wifi_ingest_reports",
+                columns: "hotspot_key, timestamp",
+            },
+            Index {
+                name: "mobile_wifi_ingest_reports_coverage_object_idx",
+                table: "mobile_wifi_ingest_reports",
+                columns: "coverage_object",
+            },
+        ]
+    }
+
+    fn partitioned(&self) -> Vec<Partitioned> {
+        vec![Partitioned {
+            table: "mobile_wifi_ingest_reports",
+            column: "received_timestamp",
+            columns: COLUMNS,
+        }]
+    }
 }
 
 #[async_trait::async_trait]
 impl Insertable for Vec<WifiHeartbeatIngestReport> {
     async fn insert(
         &self,
-        db: &Pool<Postgres>,
-        _file_timestamp: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
-        const NUM_IN_BATCH: usize = (u16::MAX / 8) as usize;
-
-        for chunk in self.chunks(NUM_IN_BATCH) {
-            QueryBuilder::new("INSERT INTO mobile_wifi_ingest_reports(received_timestamp, hotspot_key, timestamp, lat, lon, location_validation_timestamp, operation_mode, coverage_object)")
-            .push_values(chunk, |mut b, report| {
+        transaction: &mut Transaction<'_, Postgres>,
+        file_info: &FileInfo,
+        errors: &mut RecordErrors,
+    ) -> anyhow::Result<usize> {
+        const NUM_IN_BATCH: usize = (u16::MAX / 10) as usize;
 
-                let uuid = uuid::Uuid::from_slice(&report.report.coverage_object).expect("unable to create uuid");
+        let rows = errors.rows(self, |report| {
+            let uuid = uuid::Uuid::from_slice(&report.report.coverage_object)
+                .map_err(RecordError::invalid("coverage_object"))?;
+            Ok((report, uuid))
+        })?;
 
+        let mut written = 0;
+        for chunk in rows.chunks(NUM_IN_BATCH) {
+            written += QueryBuilder::new("INSERT INTO mobile_wifi_ingest_reports(received_timestamp, hotspot_key, timestamp, lat, lon, location_validation_timestamp, operation_mode, coverage_object, file_key, file_timestamp)")
+            .push_values(chunk, |mut b, (report, uuid)| {
                 b.push_bind(report.received_timestamp)
                     .push_bind(report.report.pubkey.to_string())
                     .push_bind(report.report.timestamp)
@@ -78,12 +120,40 @@ impl Insertable for Vec<WifiHeartbeatIngestReport> {
                     .push_bind(report.report.lon)
                     .push_bind(report.report.location_validation_timestamp)
                     .push_bind(report.report.operation_mode)
-                    .push_bind(uuid);
+                    .push_bind(*uuid)
+                    .push_bind(&file_info.key)
+                    .push_bind(file_info.timestamp);
             })
             .build()
-            .execute(db)
-            .await?;
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
         }
-        Ok(())
+        Ok(written as usize)
+    }
+
+    fn partition_keys(&self, _file_info: &FileInfo) -> Vec<(&'static str, DateTime<Utc>)> {
+        self.iter()
+            .map(|report| ("mobile_wifi_ingest_reports", report.received_timestamp))
+            .collect()
+    }
+}
+
+impl Record for WifiHeartbeatIngestReport {
+    fn timestamp(&self) -> Option<DateTime<Utc>> {
+        Some(self.received_timestamp)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "received_timestamp": self.received_timestamp,
+            "hotspot_key": self.report.pubkey.to_string(),
+            "timestamp": self.report.timestamp,
+            "lat": self.report.lat,
+            "lon": self.report.lon,
+            "location_validation_timestamp": self.report.location_validation_timestamp,
+            "operation_mode": self.report.operation_mode,
+            "coverage_object": uuid::Uuid::from_slice(&self.report.coverage_object).ok(),
+        })
     }
 }